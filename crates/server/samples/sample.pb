@@ -0,0 +1,2 @@
+
+	http-testsampleprotobuf
\ No newline at end of file
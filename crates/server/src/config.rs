@@ -0,0 +1,737 @@
+//! Server settings loaded from a TOML file with environment variable overrides
+//!
+//! Settings are layered with later layers taking precedence:
+//! 1. Built in defaults
+//! 2. The TOML file at `$HTTP_TEST_CONFIG` (defaults to `http_test.toml` if it exists)
+//! 3. Environment variables prefixed with `HTTP_TEST_` using `__` to separate
+//!    nested keys (for example `HTTP_TEST_STATIC_FILES__DIR=public`), variables that do
+//!    not start with the name of a section are ignored
+//!
+//! The `cors`, `routes`, `proxy`, `shaping` and `webhooks` sections can be changed while the server is running
+//! (see [`crate::reload`]), other sections need a restart.
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _};
+use figment::{
+    providers::{Env, Format as _, Serialized, Toml},
+    Figment,
+};
+use serde::{Deserialize, Serialize};
+
+/// Environment variable with the path of the config file
+pub const CONFIG_PATH_ENV_VAR: &str = "HTTP_TEST_CONFIG";
+
+/// Config file used if [`CONFIG_PATH_ENV_VAR`] is not set
+pub const DEFAULT_CONFIG_PATH: &str = "http_test.toml";
+
+/// Prefix of environment variables that override settings
+pub const ENV_VAR_PREFIX: &str = "HTTP_TEST_";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub server: ListenerConfig,
+    pub tls: Option<TlsConfig>,
+    pub cors: CorsConfig,
+    pub static_files: StaticFilesConfig,
+    pub limits: LimitsConfig,
+    pub routes: RouteGroups,
+    pub logging: LoggingConfig,
+    pub reload: ReloadConfig,
+    pub proxy: ProxyConfig,
+    pub shaping: ShapingConfig,
+    pub webhooks: WebhooksConfig,
+    pub grpc: GrpcConfig,
+
+    /// The file the config was loaded from, used to reload it
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerConfig {
+    /// Addresses to listen on in addition to the one provided by the runtime
+    pub listen: Vec<SocketAddr>,
+
+    /// Number of worker threads, defaults to the number of physical CPUs
+    pub workers: Option<usize>,
+
+    /// Accept HTTP/2 without TLS from clients with prior knowledge (h2c)
+    pub h2c: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// Addresses to accept TLS connections on
+    pub listen: Vec<SocketAddr>,
+
+    /// PEM file with the certificate chain
+    pub cert_file: PathBuf,
+
+    /// PEM file with the private key
+    pub key_file: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Allow everything, the other CORS settings are ignored when set
+    pub permissive: bool,
+
+    /// Origins allowed to make requests, `*` allows any origin
+    pub allowed_origins: Vec<String>,
+
+    /// Methods allowed in requests, empty allows any method
+    pub allowed_methods: Vec<String>,
+
+    /// Request headers allowed, empty allows any header
+    pub allowed_headers: Vec<String>,
+
+    pub supports_credentials: bool,
+
+    /// Seconds browsers may cache preflight responses
+    pub max_age: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StaticFilesConfig {
+    /// Directory served at the root, normally the output of `trunk build`
+    pub dir: PathBuf,
+    pub index_file: String,
+}
+
+/// Maximum sizes in bytes of request bodies
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub json: usize,
+    pub form: usize,
+
+    /// All parts of a `multipart/form-data` body together, only read by `/echo`
+    pub multipart: usize,
+    pub payload: usize,
+}
+
+/// Groups of routes that can be turned off
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouteGroups {
+    pub echo: bool,
+    pub anything: bool,
+    pub cookies: bool,
+    pub bytes: bool,
+    pub stream: bool,
+    pub rate_limit: bool,
+    pub samples: bool,
+    pub encoding: bool,
+    pub protocol: bool,
+    pub graphql: bool,
+    pub grpc: bool,
+    pub health: bool,
+    pub metrics: bool,
+    pub docs: bool,
+
+    /// Let anyone who can reach the server reload its config with `POST /_admin/reload`
+    pub admin: bool,
+    pub proxy: bool,
+    pub dashboard: bool,
+
+    /// Let anyone who can reach the server make it send requests to any `http://` URL
+    pub webhooks: bool,
+    pub static_files: bool,
+
+    /// Echo `TRACE` requests to any path instead of responding with 405
+    pub trace: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Used if `RUST_LOG` is not set, uses the same syntax
+    pub filter: String,
+    pub format: LogFormat,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReloadConfig {
+    /// Reload the config when the file it was loaded from changes
+    pub watch: bool,
+}
+
+/// Forwarding of traffic to other servers, each exchange is recorded (see [`crate::proxy`])
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// Forward requests for absolute `http://` URLs as sent by clients using the server as a proxy
+    pub forward: bool,
+
+    /// Forward requests under a path prefix to an upstream server
+    pub reverse: Option<ReverseProxyConfig>,
+
+    /// Changes made to the traffic passing through, applied in order
+    pub rewrite: Vec<RewriteRule>,
+
+    /// Number of exchanges kept, the oldest are dropped first
+    pub capture_limit: usize,
+
+    /// Bytes of each body kept in a capture, the rest is still forwarded
+    pub capture_body_limit: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ReverseProxyConfig {
+    /// Requests with paths starting with this prefix are forwarded with the prefix removed
+    pub prefix: String,
+
+    /// Base URL of the upstream server (for example `http://127.0.0.1:3000`), TLS is not supported
+    pub upstream: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RewriteRule {
+    pub direction: Direction,
+
+    /// Headers to add in the form `Name: Value`
+    #[serde(default)]
+    pub add_headers: Vec<String>,
+
+    /// Names of cookies to remove, `*` removes every cookie
+    #[serde(default)]
+    pub strip_cookies: Vec<String>,
+}
+
+/// Which messages a [`RewriteRule`] applies to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Requests sent to the upstream server
+    Request,
+
+    /// Responses returned to the client
+    Response,
+}
+
+/// Simulated network conditions (see [`crate::shaping`] for the format of a shape)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShapingConfig {
+    /// Let requests choose their own conditions with a header or query parameter
+    pub from_requests: bool,
+
+    /// Conditions for paths starting with a prefix, the first match is used
+    pub rules: Vec<ShapingRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ShapingRule {
+    pub prefix: String,
+
+    /// For example `latency_ms=200, bytes_per_sec=4096`
+    pub shape: String,
+}
+
+/// Defaults for webhooks sent with `POST /_webhooks/send` (see [`crate::webhooks`])
+///
+/// Each webhook can override the secret, attempts and backoff.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhooksConfig {
+    /// Key used to sign the payloads with HMAC-SHA256
+    pub secret: String,
+
+    /// Attempts made before giving up, including the first one
+    pub max_attempts: u32,
+
+    /// Milliseconds before the first retry, doubled for each retry after that
+    pub backoff_ms: u64,
+
+    /// Milliseconds to wait for the receiver to respond to each attempt
+    pub timeout_ms: u64,
+
+    /// Number of deliveries kept, the oldest are dropped first
+    pub delivery_limit: usize,
+}
+
+/// Listeners for native gRPC, which needs HTTP/2 trailers that the other listeners cannot send
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrpcConfig {
+    /// Addresses to serve the echo service on with HTTP/2 without TLS (h2c)
+    pub listen: Vec<SocketAddr>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Full,
+    Compact,
+    Pretty,
+
+    /// One JSON object per line for log shippers
+    Json,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            listen: Default::default(),
+            workers: None,
+            h2c: true,
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            permissive: true,
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: Default::default(),
+            allowed_headers: Default::default(),
+            supports_credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+impl Default for StaticFilesConfig {
+    fn default() -> Self {
+        Self {
+            dir: "dist".into(),
+            index_file: "index.html".to_string(),
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    /// Uses the same defaults as actix-web and actix-multipart
+    fn default() -> Self {
+        Self {
+            json: 2_097_152,
+            form: 16_384,
+            multipart: 52_428_800,
+            payload: 262_144,
+        }
+    }
+}
+
+impl Default for RouteGroups {
+    fn default() -> Self {
+        Self {
+            echo: true,
+            anything: true,
+            cookies: true,
+            bytes: true,
+            stream: true,
+            rate_limit: true,
+            samples: true,
+            encoding: true,
+            protocol: true,
+            graphql: true,
+            grpc: true,
+            health: true,
+            metrics: true,
+            docs: true,
+            admin: false,
+            proxy: true,
+            dashboard: true,
+            webhooks: false,
+            static_files: true,
+            trace: false,
+        }
+    }
+}
+
+impl RouteGroups {
+    /// If the group that serves `path` is enabled, paths outside every other group are static files
+    pub fn allows(&self, path: &str) -> bool {
+        let first_segment = path.trim_start_matches('/').split('/').next();
+        match first_segment.unwrap_or_default() {
+            "echo" | "echo_raw" => self.echo,
+            "anything" | "response" | "response-headers" => self.anything,
+            "cookies" | "scoped" => self.cookies,
+            "range" | "bytes" | "stream-bytes" => self.bytes,
+            "stream" | "ndjson" => self.stream,
+            "ratelimit" => self.rate_limit,
+            "samples" => self.samples,
+            "encoding" => self.encoding,
+            "protocol" => self.protocol,
+            "graphql" => self.graphql,
+            "http_test.echo.Echo" => self.grpc,
+            "healthz" | "readyz" | "version" => self.health,
+            "metrics" => self.metrics,
+            "docs" | "openapi.json" => self.docs,
+            "_admin" => self.admin,
+            "_proxy" => self.proxy,
+            "_dashboard" => self.dashboard,
+            "_webhooks" => self.webhooks,
+            _ => self.static_files,
+        }
+    }
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        Self { watch: true }
+    }
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            forward: false,
+            reverse: None,
+            rewrite: Default::default(),
+            capture_limit: 100,
+            capture_body_limit: 65_536,
+        }
+    }
+}
+
+impl ReverseProxyConfig {
+    /// The upstream URL for a request to `path` if it starts with the prefix
+    pub fn upstream_url(&self, path: &str, query: Option<&str>) -> Option<String> {
+        let prefix = self.prefix.trim_end_matches('/');
+        let rest = path.strip_prefix(prefix)?;
+        if !(rest.is_empty() || rest.starts_with('/')) {
+            return None;
+        }
+        let mut result = format!("{}{rest}", self.upstream.trim_end_matches('/'));
+        if let Some(query) = query {
+            result.push('?');
+            result.push_str(query);
+        }
+        Some(result)
+    }
+}
+
+impl RewriteRule {
+    /// Parses [`Self::add_headers`]
+    pub fn headers_to_add(
+        &self,
+    ) -> anyhow::Result<Vec<(reqwest::header::HeaderName, reqwest::header::HeaderValue)>> {
+        self.add_headers
+            .iter()
+            .map(|header| {
+                let (name, value) = header.split_once(':').with_context(|| {
+                    format!("header must be `Name: Value` but found: {header:?}")
+                })?;
+                Ok((
+                    name.trim()
+                        .parse()
+                        .with_context(|| format!("invalid header name: {name:?}"))?,
+                    value
+                        .trim()
+                        .parse()
+                        .with_context(|| format!("invalid header value: {value:?}"))?,
+                ))
+            })
+            .collect()
+    }
+
+    /// If the cookie called `name` should be removed
+    pub fn strips_cookie(&self, name: &str) -> bool {
+        self.strip_cookies.iter().any(|x| x == "*" || x == name)
+    }
+}
+
+impl Default for ShapingConfig {
+    fn default() -> Self {
+        Self {
+            from_requests: true,
+            rules: Default::default(),
+        }
+    }
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            secret: "http-test-webhook-secret".to_string(),
+            max_attempts: 3,
+            backoff_ms: 1000,
+            timeout_ms: 10_000,
+            delivery_limit: 100,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            filter: "info".to_string(),
+            format: Default::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Loads the config from the default locations (see module documentation) and validates it
+    pub fn load() -> anyhow::Result<Self> {
+        let path = std::env::var_os(CONFIG_PATH_ENV_VAR).map(PathBuf::from);
+        if let Some(path) = path.as_ref() {
+            if !path.exists() {
+                bail!(
+                    "config file set by {CONFIG_PATH_ENV_VAR} does not exist: {}",
+                    path.display()
+                );
+            }
+        }
+        Self::load_from(path.as_deref().unwrap_or(Path::new(DEFAULT_CONFIG_PATH)))
+    }
+
+    /// Loads the config using the file at `path` (skipped if it does not exist) and validates it
+    pub fn load_from(path: &Path) -> anyhow::Result<Self> {
+        let sections = Self::sections();
+        let mut result: Self = Figment::from(Serialized::defaults(Self::default()))
+            .merge(Toml::file_exact(path))
+            .merge(
+                Env::prefixed(ENV_VAR_PREFIX)
+                    .filter(move |key| {
+                        let section = key.as_str().split("__").next().unwrap_or_default();
+                        sections.iter().any(|x| section.eq_ignore_ascii_case(x))
+                    })
+                    .split("__"),
+            )
+            .extract()
+            .context("failed to load server config")?;
+        result.validate()?;
+        result.source = Some(path.to_path_buf());
+        Ok(result)
+    }
+
+    /// Names of the top level sections, taken from the serialized defaults so none are missed
+    fn sections() -> Vec<String> {
+        match serde_json::to_value(Self::default()) {
+            Ok(serde_json::Value::Object(map)) => map.into_iter().map(|(key, _)| key).collect(),
+            _ => unreachable!("the config serializes to a map"),
+        }
+    }
+
+    /// Parses the config from a TOML string without any other layers and validates it
+    pub fn from_toml(toml: &str) -> anyhow::Result<Self> {
+        let result: Self = Figment::from(Serialized::defaults(Self::default()))
+            .merge(Toml::string(toml))
+            .extract()
+            .context("failed to parse server config")?;
+        result.validate()?;
+        Ok(result)
+    }
+
+    /// Checks for settings that would fail at runtime, reporting all problems found
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = vec![];
+
+        if self.server.workers == Some(0) {
+            problems.push("server.workers must be at least 1".to_string());
+        }
+
+        if let Some(tls) = self.tls.as_ref() {
+            if tls.listen.is_empty() {
+                problems.push("tls.listen must have at least one address".to_string());
+            }
+            for (name, path) in [
+                ("tls.cert_file", &tls.cert_file),
+                ("tls.key_file", &tls.key_file),
+            ] {
+                if !path.is_file() {
+                    problems.push(format!("{name} does not exist: {}", path.display()));
+                }
+            }
+        }
+
+        if !self.cors.permissive {
+            for origin in self.cors.allowed_origins.iter().filter(|x| *x != "*") {
+                if !(origin.starts_with("http://") || origin.starts_with("https://")) {
+                    problems.push(format!(
+                        "cors.allowed_origins must be `*` or start with http:// or https:// but found: {origin:?}"
+                    ));
+                }
+            }
+            for method in self.cors.allowed_methods.iter() {
+                if actix_web::http::Method::from_bytes(method.as_bytes()).is_err() {
+                    problems.push(format!(
+                        "cors.allowed_methods has an invalid method: {method:?}"
+                    ));
+                }
+            }
+            if self.cors.supports_credentials && self.cors.allowed_origins.iter().any(|x| x == "*")
+            {
+                problems.push(
+                    "cors.supports_credentials cannot be used when any origin is allowed"
+                        .to_string(),
+                );
+            }
+        }
+
+        if self.static_files.index_file.is_empty() {
+            problems.push("static_files.index_file must not be empty".to_string());
+        }
+
+        for (name, value) in [
+            ("limits.json", self.limits.json),
+            ("limits.form", self.limits.form),
+            ("limits.multipart", self.limits.multipart),
+            ("limits.payload", self.limits.payload),
+        ] {
+            if value == 0 {
+                problems.push(format!("{name} must be greater than 0"));
+            }
+        }
+
+        if let Some(reverse) = self.proxy.reverse.as_ref() {
+            if !reverse.prefix.starts_with('/') {
+                problems.push(format!(
+                    "proxy.reverse.prefix must start with `/` but found: {:?}",
+                    reverse.prefix
+                ));
+            }
+            match reqwest::Url::parse(&reverse.upstream) {
+                Ok(url) if url.scheme() == "http" => {}
+                _ => problems.push(format!(
+                    "proxy.reverse.upstream must be an http:// URL but found: {:?}",
+                    reverse.upstream
+                )),
+            }
+        }
+        for rule in self.proxy.rewrite.iter() {
+            if let Err(e) = rule.headers_to_add() {
+                problems.push(format!("proxy.rewrite.add_headers is invalid: {e:#}"));
+            }
+        }
+
+        for rule in self.shaping.rules.iter() {
+            if let Err(e) = rule.shape.parse::<crate::shaping::Shape>() {
+                problems.push(format!(
+                    "shaping.rules has an invalid shape for {:?}: {e:#}",
+                    rule.prefix
+                ));
+            }
+        }
+
+        if self.webhooks.secret.is_empty() {
+            problems.push("webhooks.secret must not be empty".to_string());
+        }
+        for (name, value) in [
+            ("webhooks.max_attempts", self.webhooks.max_attempts as u64),
+            ("webhooks.timeout_ms", self.webhooks.timeout_ms),
+        ] {
+            if value == 0 {
+                problems.push(format!("{name} must be greater than 0"));
+            }
+        }
+
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.logging.filter) {
+            problems.push(format!(
+                "logging.filter is invalid ({e}): {:?}",
+                self.logging.filter
+            ));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            bail!("invalid server config:\n  - {}", problems.join("\n  - "))
+        }
+    }
+
+    /// Names of the sections that differ from `other` and only take effect after a restart
+    pub fn restart_required(&self, other: &Self) -> Vec<&'static str> {
+        let mut result = vec![];
+        if self.server != other.server {
+            result.push("server");
+        }
+        if self.tls != other.tls {
+            result.push("tls");
+        }
+        if self.static_files != other.static_files {
+            result.push("static_files");
+        }
+        if self.limits != other.limits {
+            result.push("limits");
+        }
+        if self.logging != other.logging {
+            result.push("logging");
+        }
+        if self.reload != other.reload {
+            result.push("reload");
+        }
+        if self.grpc != other.grpc {
+            result.push("grpc");
+        }
+        result
+    }
+
+    /// Builds the CORS middleware described by the config
+    pub fn cors(&self) -> actix_cors::Cors {
+        let config = &self.cors;
+        if config.permissive {
+            return actix_cors::Cors::permissive();
+        }
+        let mut result = actix_cors::Cors::default();
+        for origin in config.allowed_origins.iter() {
+            result = if origin == "*" {
+                result.allow_any_origin()
+            } else {
+                result.allowed_origin(origin)
+            };
+        }
+        result = if config.allowed_methods.is_empty() {
+            result.allow_any_method()
+        } else {
+            result.allowed_methods(config.allowed_methods.iter().map(String::as_str))
+        };
+        result = if config.allowed_headers.is_empty() {
+            result.allow_any_header()
+        } else {
+            result.allowed_headers(config.allowed_headers.iter().map(String::as_str))
+        };
+        if config.supports_credentials {
+            result = result.supports_credentials();
+        }
+        result.max_age(config.max_age)
+    }
+}
+
+impl TlsConfig {
+    /// Reads the certificate and key files
+    pub fn rustls_config(&self) -> anyhow::Result<rustls::ServerConfig> {
+        let cert_file = std::fs::File::open(&self.cert_file)
+            .with_context(|| format!("failed to open {}", self.cert_file.display()))?;
+        let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(cert_file))
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| {
+                format!(
+                    "failed to read certificates from {}",
+                    self.cert_file.display()
+                )
+            })?;
+        let key_file = std::fs::File::open(&self.key_file)
+            .with_context(|| format!("failed to open {}", self.key_file.display()))?;
+        let key = rustls_pemfile::private_key(&mut std::io::BufReader::new(key_file))
+            .with_context(|| {
+                format!(
+                    "failed to read private key from {}",
+                    self.key_file.display()
+                )
+            })?
+            .with_context(|| format!("no private key found in {}", self.key_file.display()))?;
+        rustls::ServerConfig::builder_with_provider(rustls::crypto::ring::default_provider().into())
+            .with_safe_default_protocol_versions()
+            .context("failed to select TLS protocol versions")?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .context("invalid TLS certificate or key")
+    }
+}
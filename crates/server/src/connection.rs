@@ -0,0 +1,68 @@
+//! Details of the connection a request arrived on so clients can check how they connect
+use std::{
+    any::Any,
+    cell::Cell,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    rt::net::TcpStream,
+    HttpMessage as _,
+};
+
+/// Stored in the connection's extensions when it is accepted
+pub struct ConnectionInfo {
+    /// Unique for the life of the process
+    pub id: u64,
+    pub tls: bool,
+
+    /// Protocol selected by ALPN during the TLS handshake
+    pub alpn: Option<String>,
+
+    /// Number of requests started on the connection so far
+    requests: Cell<u64>,
+}
+
+/// Position of the request on its connection starting from 1
+#[derive(Debug, Clone, Copy)]
+pub struct RequestNumber(pub u64);
+
+impl ConnectionInfo {
+    /// `io` is the stream passed to the server's `on_connect` callback
+    pub fn new(io: &dyn Any) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        let tls = io.downcast_ref::<TlsStream<TcpStream>>();
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            tls: tls.is_some(),
+            alpn: tls
+                .and_then(|x| x.get_ref().1.alpn_protocol())
+                .map(|x| String::from_utf8_lossy(x).into_owned()),
+            requests: Cell::new(0),
+        }
+    }
+
+    fn start_request(&self) -> RequestNumber {
+        let result = self.requests.get() + 1;
+        self.requests.set(result);
+        RequestNumber(result)
+    }
+}
+
+/// Middleware that numbers each request on its connection, see [`RequestNumber`]
+pub async fn count_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> actix_web::Result<ServiceResponse<impl MessageBody>> {
+    if let Some(number) = req
+        .conn_data::<ConnectionInfo>()
+        .map(ConnectionInfo::start_request)
+    {
+        req.extensions_mut().insert(number);
+    }
+    next.call(req).await
+}
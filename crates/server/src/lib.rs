@@ -1,57 +1,223 @@
 use std::collections::HashMap;
 
-use actix_cors::Cors;
 use actix_files::Files;
+use actix_multipart::Multipart;
 use actix_web::{
+    error,
+    http::{header::HeaderMap, StatusCode},
+    middleware::{from_fn, ErrorHandlers},
     web::{self, scope, ServiceConfig},
-    App, HttpRequest, HttpResponse, HttpServer, ResponseError,
+    App, HttpMessage as _, HttpRequest, HttpResponse, HttpServer, ResponseError,
+};
+use anyhow::{anyhow, Context as _};
+use config::LimitsConfig;
+use futures_util::StreamExt as _;
+use methods::any_method;
+use reload::LiveConfig;
+use routes::{
+    admin_reload, anything, captures_clear, captures_list, cookie_expire, cookie_set, cookie_show,
+    cookie_stress, cookie_stress_list, dashboard_events, dashboard_page, docs_service,
+    encoding_list, encoding_show, graphql, graphql_echo, grpc_echo, health_check, metrics_show,
+    ndjson, protocol, random_bytes, range, rate_limit_reset, rate_limit_status, rate_limit_take,
+    readiness_check, response, response_headers, sample_show, samples_list,
+    scoped_cookies_expected, scoped_cookies_set, scoped_cookies_show, stream_bytes, stream_json,
+    stream_json_checksum, version_show, webhook_deliveries, webhook_deliveries_clear, webhook_send,
+    RateLimitBuckets, ServerInfo,
 };
-use routes::{cookie_expire, cookie_set, cookie_show};
 use thiserror::Error;
-use tracing::{error, instrument};
+use tracing::{error, instrument, warn};
 use tracing_actix_web::TracingLogger;
 
+pub mod config;
+mod connection;
+mod methods;
+mod metrics;
+mod problem;
+mod proxy;
+mod recent_requests;
+pub mod reload;
+mod request_id;
 mod routes;
+mod shaping;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod webhooks;
+
+pub use config::ServerConfig;
+pub use problem::{ErrorKind, ProblemDetails};
+pub use request_id::{RequestId, REQUEST_ID_HEADER};
+pub use routes::ApiDoc;
 
 #[derive(Error, Debug)]
-#[error(transparent)]
-pub struct HandlerError(#[from] anyhow::Error);
+#[error("{error:#}")]
+pub struct HandlerError {
+    kind: ErrorKind,
+    error: anyhow::Error,
+}
 pub type Result<T, E = HandlerError> = core::result::Result<T, E>;
 
+impl HandlerError {
+    pub fn new(kind: ErrorKind, error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            kind,
+            error: error.into(),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn source_error(&self) -> &anyhow::Error {
+        &self.error
+    }
+}
+
+/// Errors without a more specific kind are treated as server bugs
+impl From<anyhow::Error> for HandlerError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::new(ErrorKind::Internal, error)
+    }
+}
+
 /// This function is called once per worker
-fn modify_service_config(cfg: &mut ServiceConfig) {
-    cfg.service(scope("/echo").default_service(web::route().to(echo_handler)))
-        .service(scope("/echo_raw").default_service(web::route().to(echo_raw_handler)))
+///
+/// Every route group is registered, [`reload::filter_route_groups`] turns them
+/// off so they can be changed without a restart.
+fn modify_service_config(cfg: &mut ServiceConfig, config: &ServerConfig) {
+    cfg.app_data(web::JsonConfig::default().limit(config.limits.json))
+        .app_data(web::FormConfig::default().limit(config.limits.form))
+        .app_data(web::PayloadConfig::default().limit(config.limits.payload))
+        .app_data(web::Data::new(config.limits.clone()));
+    cfg.service(
+        scope("/echo")
+            .service(any_method("", echo_handler))
+            .service(any_method("/{_:.*}", echo_handler)),
+    )
+    .service(
+        scope("/echo_raw")
+            .service(any_method("", echo_raw_handler))
+            .service(any_method("/{_:.*}", echo_raw_handler)),
+    );
+    cfg.service(web::resource("/graphql").get(graphql).post(graphql))
         .service(
-            scope("/cookies")
-                .route("/", web::get().to(cookie_show))
-                .route("/delete/{name}", web::get().to(cookie_expire))
-                .route("/set/{name}/{value}", web::get().to(cookie_set)),
-        )
-        .service(Files::new("/", "dist").index_file("index.html"))
-        .default_service(web::route().to(not_found));
+            web::resource("/graphql/echo")
+                .get(graphql_echo)
+                .post(graphql_echo),
+        );
+    cfg.service(web::resource("/http_test.echo.Echo/{method}").post(grpc_echo));
+    cfg.service(any_method("/anything", anything))
+        .service(any_method("/anything/{_:.*}", anything))
+        .service(any_method("/response", response))
+        .service(web::resource("/response-headers").get(response_headers));
+    cfg.service(
+        scope("/cookies")
+            .service(web::resource("/").get(cookie_show))
+            .service(web::resource("/delete/{name}").get(cookie_expire))
+            .service(web::resource("/set/{name}/{value}").get(cookie_set))
+            .service(web::resource("/stress").get(cookie_stress_list))
+            .service(web::resource("/stress/{variant}").get(cookie_stress)),
+    );
+    cfg.service(
+        scope("/scoped")
+            .service(web::resource("").get(scoped_cookies_show))
+            .service(web::resource("/a").get(scoped_cookies_show))
+            .service(web::resource("/a/b").get(scoped_cookies_show))
+            .service(web::resource("/ab").get(scoped_cookies_show))
+            .service(web::resource("/set").get(scoped_cookies_set))
+            .service(web::resource("/expected").get(scoped_cookies_expected)),
+    );
+    cfg.service(docs_service());
+    cfg.service(web::resource("/healthz").get(health_check))
+        .service(web::resource("/readyz").get(readiness_check))
+        .service(web::resource("/version").get(version_show));
+    cfg.service(web::resource("/metrics").get(metrics_show));
+    cfg.service(web::resource("/range/{n}").get(range))
+        .service(web::resource("/bytes/{n}").get(random_bytes))
+        .service(web::resource("/stream-bytes/{n}").get(stream_bytes));
+    cfg.service(web::resource("/stream/{n}").get(stream_json))
+        .service(web::resource("/stream/{n}/checksum").get(stream_json_checksum))
+        .service(web::resource("/ndjson/{n}").get(ndjson));
+    cfg.service(
+        scope("/ratelimit")
+            .service(
+                web::resource("/{bucket}")
+                    .get(rate_limit_take)
+                    .post(rate_limit_take),
+            )
+            .service(web::resource("/{bucket}/status").get(rate_limit_status))
+            .service(web::resource("/{bucket}/reset").post(rate_limit_reset)),
+    );
+    cfg.service(web::resource("/samples").get(samples_list))
+        .service(web::resource("/samples/{kind}").get(sample_show));
+    cfg.service(web::resource("/encoding").get(encoding_list))
+        .service(web::resource("/encoding/{charset}").get(encoding_show));
+    cfg.service(web::resource("/protocol").get(protocol));
+    cfg.service(scope("/_admin").service(web::resource("/reload").post(admin_reload)));
+    cfg.service(
+        scope("/_proxy").service(
+            web::resource("/captures")
+                .get(captures_list)
+                .delete(captures_clear),
+        ),
+    );
+    cfg.service(
+        scope("/_dashboard")
+            .service(web::resource("").get(dashboard_page))
+            .service(web::resource("/events").get(dashboard_events)),
+    );
+    cfg.service(
+        scope("/_webhooks")
+            .service(web::resource("/send").post(webhook_send))
+            .service(
+                web::resource("/deliveries")
+                    .get(webhook_deliveries)
+                    .delete(webhook_deliveries_clear),
+            ),
+    );
+    cfg.service(
+        Files::new("/", &config.static_files.dir)
+            .index_file(&config.static_files.index_file)
+            .default_handler(web::route().to(not_found)),
+    );
+    cfg.default_service(web::route().to(not_found));
+}
+
+pub struct CustomShuttleService {
+    config: ServerConfig,
 }
 
-pub struct CustomShuttleService {}
+impl CustomShuttleService {
+    pub fn new(config: ServerConfig) -> Self {
+        Self { config }
+    }
+}
 
 #[shuttle_runtime::async_trait]
 impl shuttle_runtime::Service for CustomShuttleService {
     async fn bind(mut self, addr: std::net::SocketAddr) -> Result<(), shuttle_runtime::Error> {
-        run_server(addr).await?;
+        run_server(addr, self.config).await?;
         Ok(())
     }
 }
 
-async fn run_server(addr: std::net::SocketAddr) -> std::io::Result<()> {
-    let server = HttpServer::new(|| {
-        App::new()
-            .wrap(Cors::permissive())
-            .wrap(TracingLogger::default())
-            .configure(setup_closure())
-    })
-    .bind(addr)?
-    .run();
-    match tokio::spawn(server).await {
+async fn run_server(addr: std::net::SocketAddr, config: ServerConfig) -> anyhow::Result<()> {
+    let mut listeners =
+        vec![std::net::TcpListener::bind(addr)
+            .with_context(|| format!("failed to bind to {addr}"))?];
+    for addr in config.server.listen.iter() {
+        listeners.push(
+            std::net::TcpListener::bind(addr)
+                .with_context(|| format!("failed to bind to {addr}"))?,
+        );
+    }
+    let grpc_listeners = bind_grpc(&config)?;
+    let (server, grpc) = create_server(config, listeners, grpc_listeners)?;
+    let outcome = tokio::spawn(server).await;
+    for task in grpc {
+        task.abort();
+    }
+    match outcome {
         Ok(server_outcome) => match server_outcome {
             Ok(()) => {}
             Err(err_msg) => error!(?err_msg, "server returned with error"),
@@ -61,33 +227,163 @@ async fn run_server(addr: std::net::SocketAddr) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Seconds requests in flight get to finish when the server stops
+///
+/// Kept short as the dashboard's event streams only end when the connection is closed.
+const SHUTDOWN_TIMEOUT_SECS: u64 = 5;
+
+/// Binds the addresses in the `grpc` config section for [`routes::serve_native`]
+fn bind_grpc(config: &ServerConfig) -> anyhow::Result<Vec<std::net::TcpListener>> {
+    config
+        .grpc
+        .listen
+        .iter()
+        .map(|addr| {
+            std::net::TcpListener::bind(addr)
+                .with_context(|| format!("failed to bind native gRPC listener to {addr}"))
+        })
+        .collect()
+}
+
+/// Tasks serving native gRPC, they keep running until aborted
+type GrpcTasks = Vec<tokio::task::JoinHandle<anyhow::Result<()>>>;
+
+/// Creates the server with all middleware listening on `listeners` and any TLS addresses in `config`
+///
+/// Native gRPC is served on `grpc_listeners` by tasks sharing the server's live config.
+fn create_server(
+    config: ServerConfig,
+    listeners: Vec<std::net::TcpListener>,
+    grpc_listeners: Vec<std::net::TcpListener>,
+) -> anyhow::Result<(actix_web::dev::Server, GrpcTasks)> {
+    let workers = config.server.workers;
+    let h2c = config.server.h2c;
+    let tls = config
+        .tls
+        .as_ref()
+        .map(|tls| anyhow::Ok((tls.listen.clone(), tls.rustls_config()?)))
+        .transpose()?;
+    let live_config = web::Data::new(LiveConfig::new(config.clone()));
+    let app_config = setup_closure(config, live_config.clone());
+    let mut server = HttpServer::new(move || {
+        App::new()
+            .wrap(from_fn(methods::handle_methods))
+            .wrap(from_fn(reload::filter_route_groups))
+            .wrap(from_fn(proxy::proxy_requests))
+            .wrap(from_fn(shaping::shape_responses))
+            .wrap(ErrorHandlers::new().default_handler(problem::add_request_id))
+            .wrap(reload::LiveCors)
+            .wrap(TracingLogger::<request_id::RequestIdRootSpan>::new())
+            .wrap(from_fn(recent_requests::record_requests))
+            .wrap(from_fn(request_id::propagate_request_id))
+            .wrap(from_fn(metrics::record_metrics))
+            .wrap(from_fn(connection::count_requests))
+            .configure(app_config.clone())
+    })
+    .on_connect(|io, ext| {
+        ext.insert(metrics::ConnectionGuard::new());
+        ext.insert(connection::ConnectionInfo::new(io));
+    })
+    .shutdown_timeout(SHUTDOWN_TIMEOUT_SECS);
+    if let Some(workers) = workers {
+        server = server.workers(workers);
+    }
+    for listener in listeners {
+        server = if h2c {
+            server.listen_auto_h2c(listener)?
+        } else {
+            server.listen(listener)?
+        };
+    }
+    if let Some((addresses, tls_config)) = tls {
+        for addr in addresses {
+            server = server
+                .bind_rustls_0_23(addr, tls_config.clone())
+                .with_context(|| format!("failed to bind TLS listener to {addr}"))?;
+        }
+    }
+    let grpc = grpc_listeners
+        .into_iter()
+        .map(|listener| {
+            tokio::spawn(routes::serve_native(
+                listener,
+                live_config.clone().into_inner(),
+            ))
+        })
+        .collect();
+    Ok((server.run(), grpc))
+}
+
 /// This function is called once and returns a closure that is called once per worker
-pub fn setup_closure() -> impl FnOnce(&mut ServiceConfig) + Send + Clone + 'static {
+pub fn setup_closure(
+    config: ServerConfig,
+    live_config: web::Data<LiveConfig>,
+) -> impl FnOnce(&mut ServiceConfig) + Send + Clone + 'static {
     // Code that should run exactly once
+    let server_info = web::Data::new(ServerInfo::default());
+    let rate_limit_buckets = web::Data::new(RateLimitBuckets::default());
+    let proxy = web::Data::new(proxy::Proxy::default());
+    let recent_requests = web::Data::new(recent_requests::RecentRequests::default());
+    let webhooks = web::Data::new(webhooks::Webhooks::default());
+    if config.reload.watch {
+        if let Err(err) = live_config.clone().into_inner().watch() {
+            warn!("config file will not be reloaded automatically: {err:#}");
+        }
+    }
 
     // Closure that is returned
-    |cfg: &mut ServiceConfig| {
-        modify_service_config(cfg);
+    move |cfg: &mut ServiceConfig| {
+        cfg.app_data(server_info)
+            .app_data(rate_limit_buckets)
+            .app_data(live_config)
+            .app_data(proxy)
+            .app_data(recent_requests)
+            .app_data(webhooks);
+        modify_service_config(cfg, &config);
     }
 }
 
 #[tracing::instrument(name = "DEFAULT NOT FOUND HANDLER", level = "error")]
 pub async fn not_found(req: HttpRequest) -> HttpResponse {
-    HttpResponse::NotFound().body("404 - Not found\n")
+    ProblemDetails::new(
+        ErrorKind::NotFound,
+        format!("no resource found at {}", req.path()),
+    )
+    .with_request_id_of(&req)
+    .to_response()
 }
 
 impl ResponseError for HandlerError {
     fn status_code(&self) -> actix_web::http::StatusCode {
-        actix_web::http::StatusCode::IM_A_TEAPOT
+        self.kind.status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        ProblemDetails::from(self).to_response()
     }
 }
 
+/// Echos back the raw request and body without trying to parse the body
+#[utoipa::path(
+    method(get, post, put, patch, delete),
+    path = "/echo_raw",
+    tag = "echo",
+    request_body(content = Vec<u8>, description = "Any body", content_type = "application/octet-stream"),
+    responses((status = 200, description = "Debug dump of the request and body", body = String))
+)]
 #[instrument]
 pub async fn echo_raw_handler(req: HttpRequest, bytes: web::Bytes) -> HttpResponse {
+    let request_id = RequestId::of(&req)
+        .map(|x| x.to_string())
+        .unwrap_or_default();
     HttpResponse::Ok().body(format!(
         "\
 ECHO RAW RESPONSE
 
+-- request id --
+{request_id}
+--------------------------------------------------------
+
 -- req --
 {req:#?}
 --------------------------------------------------------
@@ -97,16 +393,138 @@ ECHO RAW RESPONSE
     ))
 }
 
-#[instrument]
+/// A part of a `multipart/form-data` body, only the size of the content is kept
+struct MultipartPart {
+    name: Option<String>,
+    filename: Option<String>,
+    content_type: Option<String>,
+    size: usize,
+}
+
+impl std::fmt::Display for MultipartPart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "name: {}, filename: {}, content type: {}, size: {} bytes",
+            self.name.as_deref().unwrap_or("-"),
+            self.filename.as_deref().unwrap_or("-"),
+            self.content_type.as_deref().unwrap_or("-"),
+            self.size
+        )
+    }
+}
+
+/// Reads every part of a `multipart/form-data` body, failing once `limit` bytes have been read
+async fn read_multipart(
+    headers: &HeaderMap,
+    payload: web::Payload,
+    limit: usize,
+) -> actix_web::Result<Vec<MultipartPart>> {
+    let mut multipart = Multipart::new(headers, payload);
+    let mut parts = Vec::new();
+    let mut total = 0;
+    while let Some(field) = multipart.next().await {
+        let mut field = field?;
+        let disposition = field.content_disposition();
+        let mut part = MultipartPart {
+            name: field.name().map(str::to_string),
+            filename: disposition.and_then(|x| x.get_filename().map(str::to_string)),
+            content_type: field.content_type().map(|x| x.to_string()),
+            size: 0,
+        };
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk?;
+            part.size += chunk.len();
+            total += chunk.len();
+            if total > limit {
+                return Err(error::ErrorPayloadTooLarge(format!(
+                    "multipart body is larger than allowed (limit: {limit} bytes)"
+                )));
+            }
+        }
+        parts.push(part);
+    }
+    Ok(parts)
+}
+
+/// The body from an extractor, errors are added to `errors` to be included in the echo
+///
+/// Bodies over the limit fail the request and content type mismatches are ignored as the
+/// body is then meant for another extractor.
+fn extracted<T>(
+    name: &str,
+    result: actix_web::Result<T>,
+    errors: &mut Vec<String>,
+) -> Result<Option<T>> {
+    let err = match result {
+        Ok(value) => return Ok(Some(value)),
+        Err(err) => err,
+    };
+    let other_content_type = matches!(
+        err.as_error::<error::JsonPayloadError>(),
+        Some(error::JsonPayloadError::ContentType)
+    ) || matches!(
+        err.as_error::<error::UrlencodedError>(),
+        Some(error::UrlencodedError::ContentType)
+    );
+    if other_content_type {
+        return Ok(None);
+    }
+    if err.as_response_error().status_code() == StatusCode::PAYLOAD_TOO_LARGE {
+        return Err(HandlerError::new(
+            ErrorKind::PayloadTooLarge,
+            anyhow!("{err}"),
+        ));
+    }
+    errors.push(format!("{name}: {err}"));
+    Ok(None)
+}
+
+/// Echos back the request along with the body parsed as JSON, a form or multipart (if possible)
+///
+/// Errors from parsing the body are listed in the response, except for bodies over the
+/// configured limits which are rejected with 413.
+#[utoipa::path(
+    method(get, post, put, patch, delete),
+    path = "/echo",
+    tag = "echo",
+    responses(
+        (status = 200, description = "Debug dump of the request, parsed body and parsing errors", body = String),
+        (status = 413, description = "Body larger than the configured limit", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip(payload))]
 pub async fn echo_handler(
     req: HttpRequest,
-    json: Option<web::Json<serde_json::Value>>,
-    form: Option<web::Form<HashMap<String, String>>>,
-) -> HttpResponse {
-    HttpResponse::Ok().body(format!(
+    json: actix_web::Result<web::Json<serde_json::Value>>,
+    form: actix_web::Result<web::Form<HashMap<String, String>>>,
+    payload: web::Payload,
+    limits: web::Data<LimitsConfig>,
+) -> Result<HttpResponse> {
+    let request_id = RequestId::of(&req)
+        .map(|x| x.to_string())
+        .unwrap_or_default();
+    let mut errors = Vec::new();
+    let json = extracted("json", json, &mut errors)?;
+    let form = extracted("form", form, &mut errors)?;
+    let multipart = if req
+        .content_type()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        let parts = read_multipart(req.headers(), payload, limits.multipart).await;
+        extracted("multipart", parts, &mut errors)?
+            .map(|parts| parts.iter().map(ToString::to_string).collect::<Vec<_>>())
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().body(format!(
         "\
 ECHO RESPONSE
 
+-- request id --
+{request_id}
+--------------------------------------------------------
+
 -- req --
 {req:#?}
 --------------------------------------------------------
@@ -117,7 +535,15 @@ ECHO RESPONSE
 
 -- json --
 {json:#?}
+--------------------------------------------------------
+
+-- multipart --
+{multipart:#?}
+--------------------------------------------------------
+
+-- errors --
+{errors:#?}
 
 "
-    ))
+    )))
 }
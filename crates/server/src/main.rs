@@ -1,4 +1,4 @@
-use http_test_server::CustomShuttleService;
+use http_test_server::{config::LogFormat, CustomShuttleService, ServerConfig};
 use tracing_subscriber::{
     fmt::{self, format::FmtSpan},
     prelude::*,
@@ -7,11 +7,23 @@ use tracing_subscriber::{
 
 #[shuttle_runtime::main]
 async fn main() -> Result<CustomShuttleService, shuttle_runtime::Error> {
+    let config = ServerConfig::load()?;
+
+    let fmt_layer = fmt::layer().with_span_events(FmtSpan::NEW);
+    // let fmt_layer = fmt::layer().with_span_events(FmtSpan::ACTIVE);
+    let fmt_layer = match config.logging.format {
+        LogFormat::Full => fmt_layer.boxed(),
+        LogFormat::Compact => fmt_layer.compact().boxed(),
+        LogFormat::Pretty => fmt_layer.pretty().boxed(),
+        LogFormat::Json => fmt_layer.json().boxed(),
+    };
     tracing_subscriber::registry()
-        .with(fmt::layer().with_span_events(FmtSpan::NEW))
-        // .with(fmt::layer().with_span_events(FmtSpan::ACTIVE))
-        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(fmt_layer)
+        .with(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new(&config.logging.filter)),
+        )
         .init();
 
-    Ok(CustomShuttleService {})
+    Ok(CustomShuttleService::new(config))
 }
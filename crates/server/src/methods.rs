@@ -0,0 +1,173 @@
+//! Handles `HEAD`, `OPTIONS`, `TRACE` and unsupported methods the same way for every route
+//!
+//! - `HEAD` is handled as `GET`, actix-web drops the body but keeps `Content-Length`.
+//!   Handlers that change state on `GET` check [`is_head`] to avoid doing so
+//! - `OPTIONS` gets `204 No Content` with `Allow`, except for routes that accept any
+//!   method which handle it themselves and only get `Allow` added
+//! - Other methods a route does not accept get a 405 problem with `Allow`
+//! - `TRACE` echoes the request if `routes.trace` is on, otherwise no route allows it
+use std::str::FromStr as _;
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{self, HeaderValue},
+        Method, StatusCode,
+    },
+    middleware::Next,
+    web::{self, Data},
+    FromRequest, Handler, HttpMessage as _, HttpRequest, HttpResponse, Resource, Responder,
+};
+
+use crate::{reload::LiveConfig, ErrorKind, HandlerError, ProblemDetails};
+
+/// Methods accepted by routes registered with [`any_method`]
+const ANY_METHOD: &[Method] = &[
+    Method::GET,
+    Method::HEAD,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+    Method::OPTIONS,
+];
+
+/// Request headers left out of `TRACE` responses as they are likely to hold credentials
+const SENSITIVE_HEADERS: &[header::HeaderName] = &[
+    header::AUTHORIZATION,
+    header::COOKIE,
+    header::PROXY_AUTHORIZATION,
+];
+
+/// Resource that passes every method except `TRACE` to `handler`
+///
+/// Used instead of `web::route()` so the methods are known when responding to
+/// `OPTIONS` and `TRACE`.
+pub fn any_method<F, Args>(path: &str, handler: F) -> Resource
+where
+    F: Handler<Args> + Clone,
+    Args: FromRequest + 'static,
+    F::Output: Responder + 'static,
+{
+    ANY_METHOD
+        .iter()
+        .fold(web::resource(path), |resource, method| {
+            resource.route(web::route().method(method.clone()).to(handler.clone()))
+        })
+}
+
+/// Marks requests that were sent as `HEAD` before being handled as `GET`
+#[derive(Debug, Clone, Copy)]
+struct HeadRequest;
+
+/// If the request was sent as `HEAD`, its method is `GET` by the time handlers see it
+pub fn is_head(req: &HttpRequest) -> bool {
+    req.extensions().contains::<HeadRequest>()
+}
+
+fn trace_enabled(req: &ServiceRequest) -> bool {
+    req.app_data::<Data<LiveConfig>>()
+        .is_some_and(|x| x.current().routes.trace)
+}
+
+/// The request line and headers as received, see RFC 9110 section 9.3.8
+fn trace_response(req: &ServiceRequest) -> HttpResponse {
+    let mut message = format!("{} {} {:?}\r\n", req.method(), req.uri(), req.version());
+    for (name, value) in req.headers().iter() {
+        if SENSITIVE_HEADERS.contains(name) {
+            continue;
+        }
+        message.push_str(&format!(
+            "{name}: {}\r\n",
+            String::from_utf8_lossy(value.as_bytes())
+        ));
+    }
+    message.push_str("\r\n");
+    HttpResponse::Ok()
+        .content_type("message/http")
+        .body(message)
+}
+
+/// The methods in the `Allow` header set by actix-web plus the ones handled here
+///
+/// actix-files does not set `Allow` when it rejects a method, it only serves `GET` and `HEAD`.
+fn allowed_methods<B>(res: &ServiceResponse<B>, trace: bool) -> Vec<Method> {
+    let mut result: Vec<Method> = match res.headers().get(header::ALLOW) {
+        Some(allow) => allow
+            .to_str()
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|x| Method::from_str(x.trim()).ok())
+            .collect(),
+        None => vec![Method::GET, Method::HEAD],
+    };
+    let mut handled_here = vec![Method::OPTIONS];
+    if result.contains(&Method::GET) {
+        handled_here.insert(0, Method::HEAD);
+    }
+    if trace {
+        handled_here.push(Method::TRACE);
+    }
+    for method in handled_here {
+        if !result.contains(&method) {
+            result.push(method);
+        }
+    }
+    result
+}
+
+fn allow_header(methods: &[Method]) -> HeaderValue {
+    let methods: Vec<_> = methods.iter().map(Method::as_str).collect();
+    HeaderValue::from_str(&methods.join(", ")).expect("method names are valid header values")
+}
+
+/// Middleware that applies the method handling described in the [module docs](self)
+///
+/// Must be registered directly around the routes so it sees the responses of actix-web
+/// for methods that no route accepts.
+pub async fn handle_methods<B: MessageBody + 'static>(
+    mut req: ServiceRequest,
+    next: Next<B>,
+) -> actix_web::Result<ServiceResponse<EitherBody<B>>> {
+    let method = req.method().clone();
+    let trace = trace_enabled(&req);
+    if method == Method::TRACE && trace {
+        let res = trace_response(&req);
+        return Ok(req.into_response(res).map_into_right_body());
+    }
+    if method == Method::HEAD {
+        req.head_mut().method = Method::GET;
+        req.extensions_mut().insert(HeadRequest);
+    }
+    let mut res = next.call(req).await?;
+    let is_handler_error = res
+        .response()
+        .error()
+        .is_some_and(|x| x.as_error::<HandlerError>().is_some());
+    if res.status() == StatusCode::METHOD_NOT_ALLOWED && !is_handler_error {
+        let allow = allow_header(&allowed_methods(&res, trace));
+        let response = if method == Method::OPTIONS {
+            HttpResponse::NoContent().finish()
+        } else {
+            ProblemDetails::new(
+                ErrorKind::MethodNotAllowed,
+                format!("{method} is not allowed for {}", res.request().path()),
+            )
+            .with_request_id_of(res.request())
+            .to_response()
+        };
+        let mut res = res.into_response(response);
+        res.headers_mut().insert(header::ALLOW, allow);
+        return Ok(res.map_into_right_body());
+    }
+    if method == Method::OPTIONS && !res.headers().contains_key(header::ALLOW) {
+        let mut methods = ANY_METHOD.to_vec();
+        if trace {
+            methods.push(Method::TRACE);
+        }
+        res.headers_mut()
+            .insert(header::ALLOW, allow_header(&methods));
+    }
+    Ok(res.map_into_left_body())
+}
@@ -0,0 +1,193 @@
+//! Prometheus metrics collected for every request the server handles
+use std::{
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::StatusCode,
+    middleware::Next,
+};
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+};
+
+/// Label used when a request did not match any route
+const UNMATCHED_ROUTE: &str = "<unmatched>";
+
+/// Label used for methods not in [`KNOWN_METHODS`] so clients cannot create new series
+const OTHER_METHOD: &str = "OTHER";
+
+const KNOWN_METHODS: &[&str] = &[
+    "GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS", "TRACE", "CONNECT",
+];
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Returns the process wide metrics, they are global so connections can be
+/// counted from [`actix_web::HttpServer::on_connect`] which has no access to app data
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    in_flight: IntGauge,
+    connections: IntGauge,
+    subsystem_items: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of requests handled"),
+            &["method", "route", "status"],
+        )
+        .expect("valid metric definition");
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to produce a response",
+            ),
+            &["method", "route"],
+        )
+        .expect("valid metric definition");
+        let in_flight = IntGauge::new(
+            "http_requests_in_flight",
+            "Number of requests currently being handled",
+        )
+        .expect("valid metric definition");
+        let connections = IntGauge::new("http_connections_active", "Number of open connections")
+            .expect("valid metric definition");
+        let subsystem_items = IntGaugeVec::new(
+            Opts::new(
+                "subsystem_items",
+                "Number of items currently held by each stateful subsystem",
+            ),
+            &["subsystem"],
+        )
+        .expect("valid metric definition");
+        for collector in [
+            Box::new(requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(latency.clone()),
+            Box::new(in_flight.clone()),
+            Box::new(connections.clone()),
+            Box::new(subsystem_items.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+        Self {
+            registry,
+            requests,
+            latency,
+            in_flight,
+            connections,
+            subsystem_items,
+        }
+    }
+
+    /// Sets the number of items held by a subsystem (for example rate limit buckets)
+    pub fn set_subsystem_items(&self, subsystem: &str, count: usize) {
+        self.subsystem_items
+            .with_label_values(&[subsystem])
+            .set(count as i64);
+    }
+
+    /// Counts a handled request and observes how long it took to respond
+    pub(crate) fn record_request(
+        &self,
+        method: &str,
+        route: &str,
+        status: StatusCode,
+        elapsed: Duration,
+    ) {
+        let method = if KNOWN_METHODS.contains(&method) {
+            method
+        } else {
+            OTHER_METHOD
+        };
+        self.requests
+            .with_label_values(&[method, route, status.as_str()])
+            .inc();
+        self.latency
+            .with_label_values(&[method, route])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format
+    pub fn encode(&self) -> anyhow::Result<String> {
+        Ok(prometheus::TextEncoder::new().encode_to_string(&self.registry.gather())?)
+    }
+}
+
+/// Keeps [`Metrics`] active connection count accurate by being stored in the
+/// connection's extensions and dropped when the connection closes
+pub struct ConnectionGuard(());
+
+impl ConnectionGuard {
+    #[allow(clippy::new_without_default)] // Creating one has a side effect
+    pub fn new() -> Self {
+        metrics().connections.inc();
+        Self(())
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        metrics().connections.dec();
+    }
+}
+
+/// Counts a request as in flight until dropped, even if the client disconnects and the
+/// request future is dropped before it completes
+pub(crate) struct InFlightGuard(());
+
+impl InFlightGuard {
+    pub(crate) fn new() -> Self {
+        metrics().in_flight.inc();
+        Self(())
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        metrics().in_flight.dec();
+    }
+}
+
+/// Middleware that records request counts and latency per route
+pub async fn record_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> actix_web::Result<ServiceResponse<impl MessageBody>> {
+    let method = req.method().to_string();
+    let start = Instant::now();
+    let in_flight = InFlightGuard::new();
+    let result = next.call(req).await;
+    drop(in_flight);
+
+    let (route, status) = match &result {
+        Ok(res) => (
+            match res.request().match_pattern() {
+                // The static files are mounted at the root
+                Some(pattern) if pattern.is_empty() => "/".to_string(),
+                Some(pattern) => pattern,
+                None => UNMATCHED_ROUTE.to_string(),
+            },
+            res.status(),
+        ),
+        Err(err) => (
+            UNMATCHED_ROUTE.to_string(),
+            err.as_response_error().status_code(),
+        ),
+    };
+    metrics().record_request(&method, &route, status, start.elapsed());
+    result
+}
@@ -0,0 +1,167 @@
+//! Error responses using the [RFC 9457](https://www.rfc-editor.org/rfc/rfc9457) problem details format
+use actix_web::{
+    dev::ServiceResponse,
+    http::{header, StatusCode},
+    middleware::ErrorHandlerResponse,
+    HttpRequest, HttpResponse,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{HandlerError, RequestId};
+
+pub const PROBLEM_JSON_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Categories of errors so clients can tell them apart without parsing the detail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The request was invalid
+    BadRequest,
+
+    /// The cookies sent with the request could not be read
+    InvalidCookies,
+
+    /// Nothing exists at the requested location
+    NotFound,
+
+    /// The route exists but does not accept the request's method
+    MethodNotAllowed,
+
+    /// The request body is larger than the configured limit, see [`crate::config::LimitsConfig`]
+    PayloadTooLarge,
+
+    /// The request body is in a format the route does not accept
+    UnsupportedMediaType,
+
+    /// The server config could not be loaded
+    InvalidConfig,
+
+    /// A proxied request could not be completed by the upstream server
+    BadGateway,
+
+    /// Failed on purpose to simulate an unreliable network, see [`crate::shaping`]
+    SimulatedFailure,
+
+    /// A bug or unexpected failure on the server
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ErrorKind::BadRequest | ErrorKind::InvalidCookies => StatusCode::BAD_REQUEST,
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ErrorKind::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorKind::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorKind::InvalidConfig => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorKind::BadGateway => StatusCode::BAD_GATEWAY,
+            ErrorKind::SimulatedFailure => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The problem type URI, `about:blank` when the status code says it all
+    pub fn problem_type(&self) -> &'static str {
+        match self {
+            ErrorKind::InvalidCookies => "urn:http-test:problem:invalid-cookies",
+            ErrorKind::InvalidConfig => "urn:http-test:problem:invalid-config",
+            ErrorKind::SimulatedFailure => "urn:http-test:problem:simulated-failure",
+            ErrorKind::BadRequest
+            | ErrorKind::NotFound
+            | ErrorKind::MethodNotAllowed
+            | ErrorKind::PayloadTooLarge
+            | ErrorKind::UnsupportedMediaType
+            | ErrorKind::BadGateway
+            | ErrorKind::Internal => "about:blank",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            ErrorKind::InvalidCookies => "Invalid Cookies",
+            ErrorKind::InvalidConfig => "Invalid Config",
+            ErrorKind::SimulatedFailure => "Simulated Failure",
+            ErrorKind::BadRequest
+            | ErrorKind::NotFound
+            | ErrorKind::MethodNotAllowed
+            | ErrorKind::PayloadTooLarge
+            | ErrorKind::UnsupportedMediaType
+            | ErrorKind::BadGateway
+            | ErrorKind::Internal => self
+                .status_code()
+                .canonical_reason()
+                .unwrap_or("Unknown Error"),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl ProblemDetails {
+    pub fn new(kind: ErrorKind, detail: impl Into<String>) -> Self {
+        Self {
+            problem_type: kind.problem_type(),
+            title: kind.title(),
+            status: kind.status_code().as_u16(),
+            detail: detail.into(),
+            request_id: None,
+        }
+    }
+
+    /// Sets the request ID using the one assigned to `req` (if any)
+    pub fn with_request_id_of(mut self, req: &HttpRequest) -> Self {
+        self.request_id = RequestId::of(req).map(|id| id.to_string());
+        self
+    }
+
+    pub fn to_response(&self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        HttpResponse::build(status)
+            .content_type(PROBLEM_JSON_CONTENT_TYPE)
+            .body(serde_json::to_string_pretty(self).unwrap_or_else(|_| self.detail.clone()))
+    }
+}
+
+impl From<&HandlerError> for ProblemDetails {
+    fn from(value: &HandlerError) -> Self {
+        Self::new(value.kind(), format!("{:#}", value.source_error()))
+    }
+}
+
+/// Error handler that rebuilds [`HandlerError`] responses to include the request ID
+///
+/// The ID is only available from the request, which [`actix_web::ResponseError`] does not receive.
+/// Bodies rejected for their size by the actix-web extractors are also turned into problems, their
+/// errors say which limit was exceeded. An `Allow` header set by the handler is kept.
+pub fn add_request_id<B>(res: ServiceResponse<B>) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    let Some(problem) = res.response().error().and_then(|err| {
+        let problem = match err.as_error::<HandlerError>() {
+            Some(err) => ProblemDetails::from(err),
+            None if res.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                ProblemDetails::new(ErrorKind::PayloadTooLarge, err.to_string())
+            }
+            None => return None,
+        };
+        Some(problem.with_request_id_of(res.request()))
+    }) else {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    };
+    let allow = res.headers().get(header::ALLOW).cloned();
+    let (req, _) = res.into_parts();
+    let mut response = problem.to_response();
+    if let Some(allow) = allow {
+        response.headers_mut().insert(header::ALLOW, allow);
+    }
+    let res = ServiceResponse::new(req, response);
+    Ok(ErrorHandlerResponse::Response(res.map_into_right_body()))
+}
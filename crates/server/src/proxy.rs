@@ -0,0 +1,519 @@
+//! Forward and reverse proxying with every exchange recorded for inspection
+//!
+//! Bodies are buffered so they can be recorded, streaming responses are only sent
+//! once complete. Only plain `http://` URLs can be forwarded, the `CONNECT` tunnels
+//! clients use for `https://` URLs are not supported.
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{self, Method, Version},
+    middleware::Next,
+    web::{Bytes, Data},
+    FromRequest as _, HttpRequest, HttpResponse,
+};
+use anyhow::{anyhow, Context as _};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, COOKIE, SET_COOKIE},
+    redirect,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    config::{Direction, ProxyConfig, RewriteRule},
+    reload::LiveConfig,
+    routes::BodyEncoding,
+    ErrorKind, HandlerError, RequestId, ServerConfig, REQUEST_ID_HEADER,
+};
+
+/// Headers that only apply to a single connection and are never forwarded
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[derive(Serialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyMode {
+    /// The client sent an absolute URL using the server as its proxy
+    Forward,
+
+    /// The path matched `proxy.reverse.prefix`
+    Reverse,
+}
+
+/// A request and the response to it, both as received by the proxy before any rewrite rules
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct Capture {
+    /// Increases with each exchange
+    id: u64,
+    mode: ProxyMode,
+    request_id: Option<String>,
+
+    /// Milliseconds since the Unix epoch when the request was received
+    started_at_ms: u64,
+
+    /// Milliseconds until the upstream response was complete
+    duration_ms: u64,
+    request: CapturedRequest,
+
+    /// Not set if the upstream server could not be reached
+    response: Option<CapturedResponse>,
+
+    /// Why no response was received
+    error: Option<String>,
+}
+
+/// The parts of a [`Capture`] shown on the dashboard
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct CaptureSummary {
+    id: u64,
+    mode: ProxyMode,
+    started_at_ms: u64,
+    duration_ms: u64,
+    method: String,
+    url: String,
+    status: Option<u16>,
+    error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct CapturedRequest {
+    method: String,
+
+    /// The URL the request was forwarded to
+    url: String,
+    headers: BTreeMap<String, Vec<String>>,
+    body: CapturedBody,
+}
+
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct CapturedResponse {
+    status: u16,
+    headers: BTreeMap<String, Vec<String>>,
+    body: CapturedBody,
+}
+
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct CapturedBody {
+    /// Base64 encoded if it is not valid UTF-8
+    content: String,
+    encoding: BodyEncoding,
+
+    /// Size of the whole body in bytes
+    length: usize,
+
+    /// If only the first `proxy.capture_body_limit` bytes were kept
+    truncated: bool,
+}
+
+/// Recent exchanges shared by all workers
+#[derive(Debug, Default)]
+pub struct CaptureStore {
+    last_id: AtomicU64,
+    captures: Mutex<VecDeque<Capture>>,
+}
+
+/// The HTTP client used to reach upstream servers and the exchanges recorded so far
+#[derive(Debug)]
+pub struct Proxy {
+    client: reqwest::Client,
+    pub captures: CaptureStore,
+}
+
+/// Where a proxied request is sent
+struct Target {
+    mode: ProxyMode,
+    url: String,
+}
+
+impl CapturedBody {
+    fn new(body: &[u8], limit: usize) -> Self {
+        let kept = &body[..body.len().min(limit)];
+        let (content, encoding) = match std::str::from_utf8(kept) {
+            Ok(text) => (text.to_string(), BodyEncoding::Utf8),
+            Err(_) => (STANDARD.encode(kept), BodyEncoding::Base64),
+        };
+        Self {
+            content,
+            encoding,
+            length: body.len(),
+            truncated: kept.len() < body.len(),
+        }
+    }
+}
+
+impl CaptureStore {
+    /// Oldest first
+    pub fn list(&self) -> Vec<Capture> {
+        self.lock().iter().cloned().collect()
+    }
+
+    /// Newest first
+    pub fn summaries(&self) -> Vec<CaptureSummary> {
+        self.lock()
+            .iter()
+            .rev()
+            .map(|x| CaptureSummary {
+                id: x.id,
+                mode: x.mode,
+                started_at_ms: x.started_at_ms,
+                duration_ms: x.duration_ms,
+                method: x.request.method.clone(),
+                url: x.request.url.clone(),
+                status: x.response.as_ref().map(|x| x.status),
+                error: x.error.clone(),
+            })
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Assigns the capture an ID and drops the oldest captures beyond `limit`
+    fn push(&self, mut capture: Capture, limit: usize) {
+        capture.id = self.last_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut captures = self.lock();
+        captures.push_back(capture);
+        while captures.len() > limit {
+            captures.pop_front();
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Capture>> {
+        self.captures.lock().expect("capture store mutex poisoned")
+    }
+}
+
+impl Default for Proxy {
+    fn default() -> Self {
+        Self {
+            // Redirects and cookies are passed through for the client to handle
+            client: reqwest::Client::builder()
+                .redirect(redirect::Policy::none())
+                .build()
+                .expect("failed to build proxy HTTP client"),
+            captures: Default::default(),
+        }
+    }
+}
+
+impl Target {
+    /// `None` if the request is not proxied and should be routed as usual
+    fn of(req: &ServiceRequest, config: &ProxyConfig) -> Option<crate::Result<Self>> {
+        let uri = req.uri();
+        // HTTP/2 requests always include the authority so only HTTP/1 can ask to be forwarded
+        let absolute = req.version() < Version::HTTP_2 && uri.authority().is_some();
+        if config.forward && (absolute || req.method() == Method::CONNECT) {
+            if uri.scheme_str() != Some("http") {
+                return Some(Err(HandlerError::new(
+                    ErrorKind::BadRequest,
+                    anyhow!(
+                        "only http:// URLs can be forwarded, CONNECT tunnels are not supported"
+                    ),
+                )));
+            }
+            return Some(Ok(Self {
+                mode: ProxyMode::Forward,
+                url: uri.to_string(),
+            }));
+        }
+        let url = config
+            .reverse
+            .as_ref()?
+            .upstream_url(uri.path(), uri.query())?;
+        Some(Ok(Self {
+            mode: ProxyMode::Reverse,
+            url,
+        }))
+    }
+}
+
+impl Proxy {
+    /// Sends the request upstream and records the exchange
+    async fn forward(
+        &self,
+        req: &HttpRequest,
+        target: Target,
+        body: Bytes,
+        config: &ServerConfig,
+    ) -> HttpResponse {
+        let started_at = SystemTime::now();
+        let start = Instant::now();
+        let limit = config.proxy.capture_body_limit;
+        let mut capture = Capture {
+            id: 0,
+            mode: target.mode,
+            request_id: RequestId::of(req).map(|x| x.to_string()),
+            started_at_ms: started_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            duration_ms: 0,
+            request: CapturedRequest {
+                method: req.method().to_string(),
+                url: target.url.clone(),
+                headers: header_values(
+                    req.headers()
+                        .iter()
+                        .map(|(name, value)| (name.as_str(), value.as_bytes())),
+                ),
+                body: CapturedBody::new(&body, limit),
+            },
+            response: None,
+            error: None,
+        };
+
+        let result = self.send(req, &target, body, &config.proxy.rewrite).await;
+        capture.duration_ms = start.elapsed().as_millis() as u64;
+        let response = match result {
+            Ok((status, headers, body)) => {
+                capture.response = Some(CapturedResponse {
+                    status: status.as_u16(),
+                    headers: header_values(
+                        headers
+                            .iter()
+                            .map(|(name, value)| (name.as_str(), value.as_bytes())),
+                    ),
+                    body: CapturedBody::new(&body, limit),
+                });
+                client_response(status, headers, body, &config.proxy.rewrite)
+            }
+            Err(err) => {
+                capture.error = Some(format!("{err:#}"));
+                HttpResponse::from_error(HandlerError::new(ErrorKind::BadGateway, err))
+            }
+        };
+        self.captures.push(capture, config.proxy.capture_limit);
+        response
+    }
+
+    async fn send(
+        &self,
+        req: &HttpRequest,
+        target: &Target,
+        body: Bytes,
+        rules: &[RewriteRule],
+    ) -> anyhow::Result<(reqwest::StatusCode, HeaderMap, Bytes)> {
+        let mut headers = forwardable_headers(
+            req.headers()
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_bytes())),
+        );
+        headers.remove(reqwest::header::HOST);
+        if target.mode == ProxyMode::Reverse {
+            let info = req.connection_info();
+            for (name, value) in [
+                ("x-forwarded-for", info.realip_remote_addr()),
+                ("x-forwarded-host", Some(info.host())),
+                ("x-forwarded-proto", Some(info.scheme())),
+            ] {
+                if let Some(value) = value.and_then(|x| HeaderValue::from_str(x).ok()) {
+                    headers.insert(name, value);
+                }
+            }
+        }
+        // The ID in the response replaces an invalid one from the client so send the same upstream
+        if let Some(id) = RequestId::of(req) {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(REQUEST_ID_HEADER.as_str().as_bytes()),
+                HeaderValue::from_str(id.as_str()),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+        rewrite(&mut headers, rules, Direction::Request);
+
+        let method = reqwest::Method::from_bytes(req.method().as_str().as_bytes())
+            .context("invalid method")?;
+        let resp = self
+            .client
+            .request(method, &target.url)
+            .headers(headers)
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("failed to send request to {}", target.url))?;
+        let status = resp.status();
+        let headers = resp.headers().clone();
+        let body = resp
+            .bytes()
+            .await
+            .with_context(|| format!("failed to read response from {}", target.url))?;
+        Ok((status, headers, body))
+    }
+}
+
+/// Middleware that forwards proxied requests instead of routing them, see [`ProxyConfig`]
+pub async fn proxy_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> actix_web::Result<ServiceResponse<EitherBody<impl MessageBody>>> {
+    let proxied = req
+        .app_data::<Data<LiveConfig>>()
+        .zip(req.app_data::<Data<Proxy>>())
+        .and_then(|(live, proxy)| {
+            let config = live.current();
+            let target = Target::of(&req, &config.proxy)?;
+            Some((target, config, proxy.clone()))
+        });
+    let Some((target, config, proxy)) = proxied else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let (http_req, mut payload) = req.into_parts();
+    let response = match target {
+        Ok(target) => match Bytes::from_request(&http_req, &mut payload).await {
+            Ok(body) => proxy.forward(&http_req, target, body, &config).await,
+            Err(err) => HttpResponse::from_error(err),
+        },
+        Err(err) => HttpResponse::from_error(err),
+    };
+    Ok(ServiceResponse::new(http_req, response).map_into_right_body())
+}
+
+/// The response for the client built from the upstream response
+fn client_response(
+    status: reqwest::StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    rules: &[RewriteRule],
+) -> HttpResponse {
+    let mut headers = forwardable_headers(
+        headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_bytes())),
+    );
+    rewrite(&mut headers, rules, Direction::Response);
+    let status =
+        http::StatusCode::from_u16(status.as_u16()).unwrap_or(http::StatusCode::BAD_GATEWAY);
+    let mut builder = HttpResponse::build(status);
+    for (name, value) in headers.iter() {
+        if let Ok(value) = http::header::HeaderValue::from_bytes(value.as_bytes()) {
+            builder.append_header((name.as_str(), value));
+        }
+    }
+    builder.body(body)
+}
+
+/// Copies the headers except those for the connection and the body length which is set when sent
+///
+/// Headers named in `Connection` also only apply to the connection (RFC 9110 section 7.6.1).
+fn forwardable_headers<'a>(headers: impl Iterator<Item = (&'a str, &'a [u8])>) -> HeaderMap {
+    let headers: Vec<_> = headers.collect();
+    let listed: Vec<String> = headers
+        .iter()
+        .filter(|(name, _)| *name == "connection")
+        .flat_map(|(_, value)| {
+            String::from_utf8_lossy(value)
+                .split(',')
+                .map(|x| x.trim().to_ascii_lowercase())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let mut result = HeaderMap::new();
+    for (name, value) in headers {
+        if HOP_BY_HOP.contains(&name)
+            || name == "content-length"
+            || listed.iter().any(|x| x == name)
+        {
+            continue;
+        }
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_bytes(value),
+        ) {
+            result.append(name, value);
+        }
+    }
+    result
+}
+
+fn header_values<'a>(
+    headers: impl Iterator<Item = (&'a str, &'a [u8])>,
+) -> BTreeMap<String, Vec<String>> {
+    let mut result: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for (name, value) in headers {
+        result
+            .entry(name.to_string())
+            .or_default()
+            .push(String::from_utf8_lossy(value).into_owned());
+    }
+    result
+}
+
+/// Applies the rules for `direction` in order
+fn rewrite(headers: &mut HeaderMap, rules: &[RewriteRule], direction: Direction) {
+    for rule in rules.iter().filter(|x| x.direction == direction) {
+        if !rule.strip_cookies.is_empty() {
+            match direction {
+                Direction::Request => strip_request_cookies(headers, rule),
+                Direction::Response => strip_response_cookies(headers, rule),
+            }
+        }
+        // Invalid headers are rejected when the config is validated
+        for (name, value) in rule.headers_to_add().unwrap_or_default() {
+            headers.append(name, value);
+        }
+    }
+}
+
+/// Rebuilds the `Cookie` header without the cookies the rule strips
+fn strip_request_cookies(headers: &mut HeaderMap, rule: &RewriteRule) {
+    let kept: Vec<String> = headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(';'))
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty() && !rule.strips_cookie(cookie_name(pair)))
+        .map(str::to_string)
+        .collect();
+    headers.remove(COOKIE);
+    if let Ok(value) = HeaderValue::from_str(&kept.join("; ")) {
+        if !kept.is_empty() {
+            headers.insert(COOKIE, value);
+        }
+    }
+}
+
+/// Removes the `Set-Cookie` headers for the cookies the rule strips
+fn strip_response_cookies(headers: &mut HeaderMap, rule: &RewriteRule) {
+    let kept: Vec<HeaderValue> = headers
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter(|value| {
+            !value
+                .to_str()
+                .is_ok_and(|x| rule.strips_cookie(cookie_name(x)))
+        })
+        .cloned()
+        .collect();
+    headers.remove(SET_COOKIE);
+    for value in kept {
+        headers.append(SET_COOKIE, value);
+    }
+}
+
+/// The name from `name=value` optionally followed by attributes
+fn cookie_name(cookie: &str) -> &str {
+    let pair = cookie.split(';').next().unwrap_or_default();
+    pair.split_once('=').map_or(pair, |(name, _)| name).trim()
+}
@@ -0,0 +1,138 @@
+//! Keeps a summary of the latest requests for the dashboard, see [`crate::routes::dashboard_page`]
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web::Data,
+};
+use serde::Serialize;
+use tokio::sync::watch;
+use utoipa::ToSchema;
+
+use crate::RequestId;
+
+/// Number of requests kept, older ones are dropped
+const LIMIT: usize = 200;
+
+/// Requests to paths starting with this are not kept so the dashboard does not list itself
+const DASHBOARD_PREFIX: &str = "/_dashboard";
+
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct RecentRequest {
+    /// Increases with each request
+    id: u64,
+
+    /// Milliseconds since the Unix epoch when the request was received
+    started_at_ms: u64,
+    method: String,
+
+    /// Path and query string
+    path: String,
+    version: String,
+
+    /// Not set if the request failed before a response was created
+    status: Option<u16>,
+
+    /// Milliseconds until the response headers were ready
+    duration_ms: u64,
+    client: Option<String>,
+    request_id: Option<String>,
+}
+
+/// The latest requests shared by all workers
+#[derive(Debug)]
+pub struct RecentRequests {
+    requests: Mutex<VecDeque<RecentRequest>>,
+
+    /// ID of the latest request, used to wait for new ones
+    latest: watch::Sender<u64>,
+}
+
+impl Default for RecentRequests {
+    fn default() -> Self {
+        Self {
+            requests: Default::default(),
+            latest: watch::Sender::new(0),
+        }
+    }
+}
+
+impl RecentRequests {
+    /// Newest first
+    pub fn list(&self) -> Vec<RecentRequest> {
+        self.lock().iter().rev().cloned().collect()
+    }
+
+    /// Notified each time a request is added
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.latest.subscribe()
+    }
+
+    fn push(&self, mut request: RecentRequest) {
+        let mut requests = self.lock();
+        request.id = *self.latest.borrow() + 1;
+        let id = request.id;
+        requests.push_back(request);
+        while requests.len() > LIMIT {
+            requests.pop_front();
+        }
+        drop(requests);
+        self.latest.send_replace(id);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<RecentRequest>> {
+        self.requests
+            .lock()
+            .expect("recent requests mutex poisoned")
+    }
+}
+
+/// Middleware that adds every request outside of the dashboard to [`RecentRequests`]
+///
+/// Must be registered inside of [`crate::request_id::propagate_request_id`] so the ID is available.
+pub async fn record_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> actix_web::Result<ServiceResponse<impl MessageBody>> {
+    let Some(recent) = req
+        .app_data::<Data<RecentRequests>>()
+        .cloned()
+        .filter(|_| !req.path().starts_with(DASHBOARD_PREFIX))
+    else {
+        return next.call(req).await;
+    };
+    let started_at = SystemTime::now();
+    let start = Instant::now();
+    let mut request = RecentRequest {
+        id: 0,
+        started_at_ms: started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        method: req.method().to_string(),
+        // The URI includes the scheme and host for HTTP/2 requests
+        path: req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| req.path().to_string(), |x| x.to_string()),
+        version: format!("{:?}", req.version()),
+        status: None,
+        duration_ms: 0,
+        client: req
+            .connection_info()
+            .realip_remote_addr()
+            .map(str::to_string),
+        request_id: RequestId::of(req.request()).map(|x| x.to_string()),
+    };
+    let result = next.call(req).await;
+    request.status = result.as_ref().ok().map(|res| res.status().as_u16());
+    request.duration_ms = start.elapsed().as_millis() as u64;
+    recent.push(request);
+    result
+}
@@ -0,0 +1,270 @@
+//! Changing the config while the server is running
+//!
+//! The config is reloaded when the file it came from changes (if `reload.watch` is set)
+//! or on `POST /_admin/reload`. Only the `cors`, `routes`, `proxy`, `shaping` and
+//! `webhooks` sections take effect without a restart. Requests already being handled finish with the config they
+//! started with and state held by the server (for example rate limit buckets) is kept.
+use std::{
+    cell::RefCell,
+    path::Path,
+    rc::Rc,
+    sync::{Arc, Mutex, RwLock, Weak},
+    task::{Context, Poll},
+};
+
+use actix_cors::CorsMiddleware;
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    middleware::Next,
+    web::Data,
+    Error,
+};
+use anyhow::Context as _;
+use futures_util::{
+    future::{ready, LocalBoxFuture, Ready},
+    FutureExt as _,
+};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as _};
+use serde::Serialize;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::ServerConfig;
+
+/// The config currently in use, shared by all workers
+#[derive(Debug)]
+pub struct LiveConfig {
+    current: RwLock<Snapshot>,
+
+    /// Kept so the file keeps being watched
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+#[derive(Debug, Clone)]
+struct Snapshot {
+    /// Incremented each time a different config is swapped in
+    generation: u64,
+    config: Arc<ServerConfig>,
+}
+
+/// Result of a reload
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ReloadOutcome {
+    /// Incremented each time a different config is swapped in
+    generation: u64,
+
+    /// If the config was different from the one in use
+    changed: bool,
+
+    /// Sections that changed but only take effect after a restart
+    restart_required: Vec<&'static str>,
+}
+
+impl LiveConfig {
+    pub fn new(config: ServerConfig) -> Self {
+        Self {
+            current: RwLock::new(Snapshot {
+                generation: 0,
+                config: Arc::new(config),
+            }),
+            watcher: Mutex::new(None),
+        }
+    }
+
+    pub fn current(&self) -> Arc<ServerConfig> {
+        self.snapshot().config
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.current
+            .read()
+            .expect("live config lock poisoned")
+            .clone()
+    }
+
+    /// Loads the config again from the file it was loaded from, keeping the current config on failure
+    pub fn reload(&self) -> anyhow::Result<ReloadOutcome> {
+        let current = self.current();
+        let path = current
+            .source
+            .as_deref()
+            .context("the config was not loaded from a file")?;
+        let config = ServerConfig::load_from(path)?;
+        Ok(self.replace(config))
+    }
+
+    /// Swaps in `config` for the sections that can change while running
+    pub fn replace(&self, config: ServerConfig) -> ReloadOutcome {
+        let mut guard = self.current.write().expect("live config lock poisoned");
+        let changed = *guard.config != config;
+        let restart_required = guard.config.restart_required(&config);
+        if changed {
+            guard.generation += 1;
+            guard.config = Arc::new(config);
+        }
+        ReloadOutcome {
+            generation: guard.generation,
+            changed,
+            restart_required,
+        }
+    }
+
+    /// Reloads the config whenever the file it was loaded from is written, created or removed
+    ///
+    /// The directory is watched rather than the file because editors often replace the file.
+    pub fn watch(self: &Arc<Self>) -> anyhow::Result<()> {
+        let config = self.current();
+        let Some(path) = config.source.as_deref() else {
+            return Ok(());
+        };
+        let path = std::path::absolute(path)
+            .with_context(|| format!("failed to resolve {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+        let live = Arc::downgrade(self);
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+                Ok(event) if event.paths.contains(&path) && !event.kind.is_access() => {
+                    reload_from_watcher(&live)
+                }
+                Ok(_) => {}
+                Err(err) => warn!(?err, "config file watcher failed"),
+            })
+            .context("failed to create config file watcher")?;
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("failed to watch {}", dir.display()))?;
+        *self.watcher.lock().expect("live config lock poisoned") = Some(watcher);
+        Ok(())
+    }
+}
+
+fn reload_from_watcher(live: &Weak<LiveConfig>) {
+    let Some(live) = live.upgrade() else {
+        return;
+    };
+    match live.reload() {
+        Ok(outcome) if outcome.changed => info!(?outcome, "config reloaded"),
+        Ok(_) => {}
+        Err(err) => warn!("config file changed but was not reloaded: {err:#}"),
+    }
+}
+
+/// Middleware that responds with not found for routes in groups turned off in the live config
+pub async fn filter_route_groups(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> actix_web::Result<ServiceResponse<EitherBody<impl MessageBody>>> {
+    let allowed = req
+        .app_data::<Data<LiveConfig>>()
+        .is_none_or(|live| live.current().routes.allows(req.path()));
+    if allowed {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let response = crate::not_found(req.request().clone()).await;
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+/// CORS middleware rebuilt from the live config after it changes
+pub struct LiveCors;
+
+impl<S, B> Transform<S, ServiceRequest> for LiveCors
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = LiveCorsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(LiveCorsMiddleware {
+            service: Rc::new(service),
+            cors: RefCell::new(None),
+        }))
+    }
+}
+
+type SharedCors<S> = Rc<CorsMiddleware<SharedService<S>>>;
+
+pub struct LiveCorsMiddleware<S> {
+    service: Rc<S>,
+
+    /// The CORS middleware along with the generation of the config it was built from
+    cors: RefCell<Option<(u64, SharedCors<S>)>>,
+}
+
+/// Lets each rebuilt CORS middleware wrap the same inner service
+pub struct SharedService<S>(Rc<S>);
+
+impl<S: Service<ServiceRequest>> Service<ServiceRequest> for SharedService<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        self.0.call(req)
+    }
+}
+
+impl<S, B> LiveCorsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    fn cors_for(&self, live: &LiveConfig) -> anyhow::Result<SharedCors<S>> {
+        let Snapshot { generation, config } = live.snapshot();
+        let mut cached = self.cors.borrow_mut();
+        if let Some((cached_generation, cors)) = cached.as_ref() {
+            if *cached_generation == generation {
+                return Ok(Rc::clone(cors));
+            }
+        }
+        let cors = config
+            .cors()
+            .new_transform(SharedService(Rc::clone(&self.service)))
+            .now_or_never()
+            .context("CORS middleware was not ready")?
+            .map_err(|()| anyhow::anyhow!("invalid CORS config"))?;
+        let cors = Rc::new(cors);
+        *cached = Some((generation, Rc::clone(&cors)));
+        Ok(cors)
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for LiveCorsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let cors = req
+            .app_data::<Data<LiveConfig>>()
+            .context("live config not registered")
+            .and_then(|live| self.cors_for(live));
+        match cors {
+            // The returned future does not borrow the middleware so a
+            // reload while it is pending does not affect the request
+            Ok(cors) => cors.call(req),
+            Err(err) => {
+                let err = actix_web::error::ErrorInternalServerError(format!("{err:#}"));
+                Box::pin(ready(Ok(req.error_response(err).map_into_right_body())))
+            }
+        }
+    }
+}
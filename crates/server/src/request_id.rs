@@ -0,0 +1,105 @@
+//! Identifies each request so the logs of a client can be matched to the server logs
+//!
+//! The ID is taken from the `X-Request-Id` request header or generated if it is
+//! missing, then returned in the same header on the response.
+use std::fmt;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+    Error, HttpMessage, HttpRequest,
+};
+use tracing::Span;
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+
+/// Header used to receive and return the request ID
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest request ID accepted from a client, a new one is generated for longer IDs
+const MAX_LEN: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// The ID assigned to `req` (if any)
+    pub fn of(req: &HttpRequest) -> Option<Self> {
+        req.extensions().get::<Self>().cloned()
+    }
+
+    /// Uses the ID sent by the client if it is printable ASCII and not too long
+    pub(crate) fn from_client(value: &[u8]) -> Option<Self> {
+        let value = std::str::from_utf8(value).ok()?;
+        let is_valid = !value.is_empty()
+            && value.len() <= MAX_LEN
+            && value.bytes().all(|x| x.is_ascii_graphic());
+        is_valid.then(|| Self(value.to_string()))
+    }
+
+    pub(crate) fn generate() -> Self {
+        Self(uuid::Uuid::new_v4().to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Middleware that assigns the request ID and adds it to the response
+///
+/// Must be registered outside of [`tracing_actix_web::TracingLogger`] so the ID is
+/// available when the root span is created. Errors from inner services keep the response
+/// they would have been turned into with the header added.
+pub async fn propagate_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|x| RequestId::from_client(x.as_bytes()))
+        .unwrap_or_else(RequestId::generate);
+    let header_value =
+        HeaderValue::from_str(request_id.as_str()).expect("request IDs are printable ASCII");
+    req.extensions_mut().insert(request_id);
+    let mut res = match next.call(req).await {
+        Ok(res) => res,
+        Err(err) => {
+            let mut res = err.error_response();
+            res.headers_mut().insert(REQUEST_ID_HEADER, header_value);
+            return Err(InternalError::from_response(err, res).into());
+        }
+    };
+    res.headers_mut().insert(REQUEST_ID_HEADER, header_value);
+    Ok(res)
+}
+
+/// Root span for [`tracing_actix_web::TracingLogger`] with the ID from [`propagate_request_id`]
+///
+/// Has all the fields of [`DefaultRootSpanBuilder`]. Its `request_id` field is an ID
+/// generated by tracing-actix-web, the one sent back in `X-Request-Id` is `x_request_id`.
+pub struct RequestIdRootSpan;
+
+impl RootSpanBuilder for RequestIdRootSpan {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        tracing_actix_web::root_span!(request, x_request_id = %request_id)
+    }
+
+    fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
@@ -1,2 +1,38 @@
+mod admin;
+mod anything;
+mod bytes;
+mod cookie_stress;
 mod cookies;
+mod dashboard;
+mod encoding;
+mod graphql;
+mod grpc;
+mod health;
+mod metrics;
+mod openapi;
+mod protocol;
+mod proxy;
+mod rate_limit;
+mod samples;
+mod scoped_cookies;
+mod stream;
+mod webhooks;
+pub use admin::admin_reload;
+pub use anything::{anything, response, response_headers, BodyEncoding};
+pub use bytes::{random_bytes, range, stream_bytes};
+pub use cookie_stress::{cookie_stress, cookie_stress_list};
 pub use cookies::{cookie_expire, cookie_set, cookie_show};
+pub use dashboard::{dashboard_events, dashboard_page};
+pub use encoding::{encoding_list, encoding_show};
+pub use graphql::{graphql, graphql_echo};
+pub use grpc::{grpc_echo, serve_native};
+pub use health::{health_check, readiness_check, version_show, ServerInfo};
+pub use metrics::metrics_show;
+pub use openapi::{docs_service, ApiDoc};
+pub use protocol::protocol;
+pub use proxy::{captures_clear, captures_list};
+pub use rate_limit::{rate_limit_reset, rate_limit_status, rate_limit_take, RateLimitBuckets};
+pub use samples::{sample_show, samples_list};
+pub use scoped_cookies::{scoped_cookies_expected, scoped_cookies_set, scoped_cookies_show};
+pub use stream::{ndjson, stream_json, stream_json_checksum};
+pub use webhooks::{webhook_deliveries, webhook_deliveries_clear, webhook_send};
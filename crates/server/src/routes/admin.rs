@@ -0,0 +1,29 @@
+//! Endpoints used to manage the running server
+use actix_web::web::{Data, Json};
+use tracing::instrument;
+
+use crate::{
+    reload::{LiveConfig, ReloadOutcome},
+    ErrorKind, HandlerError, ProblemDetails,
+};
+
+/// Loads the config again from the file it was loaded from
+///
+/// Only the `cors`, `routes`, `proxy`, `shaping` and `webhooks` sections take effect, changes to
+/// other sections are reported in `restart_required`. The current config is kept if the new one is invalid.
+#[utoipa::path(
+    post,
+    path = "/_admin/reload",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Config reloaded", body = ReloadOutcome),
+        (status = 422, description = "The config could not be loaded", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn admin_reload(live: Data<LiveConfig>) -> crate::Result<Json<ReloadOutcome>> {
+    let outcome = live
+        .reload()
+        .map_err(|e| HandlerError::new(ErrorKind::InvalidConfig, e))?;
+    Ok(Json(outcome))
+}
@@ -0,0 +1,304 @@
+//! Based on https://httpbin.org/#/Anything and https://httpbin.org/#/Response_inspection
+//!
+//! Responses are described by a [`ResponseSpec`] so tests can get a response of
+//! any shape without adding a route for it.
+use std::collections::BTreeMap;
+
+use actix_web::{
+    http::{
+        header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, SET_COOKIE},
+        StatusCode,
+    },
+    web::{Bytes, Query},
+    HttpMessage as _, HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use anyhow::{bail, Context as _};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::{ErrorKind, HandlerError, ProblemDetails, RequestId};
+
+/// Describes the response to return
+///
+/// As query parameters `header` and `set_cookie` may be repeated and other parameters are ignored.
+#[derive(Deserialize, ToSchema, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResponseSpec {
+    /// Status code from 200 to 999, defaults to 200
+    ///
+    /// Informational (1xx) codes are rejected as they cannot end a response.
+    status: Option<u16>,
+
+    /// Headers in the form `Name: Value`, the same name may be used more than once
+    headers: Vec<String>,
+
+    /// Sets the `Content-Type` header, replacing one from `headers`
+    content_type: Option<String>,
+
+    /// Body of the response, ignored by `/anything` which always responds with the echo
+    body: Option<String>,
+    body_encoding: BodyEncoding,
+
+    /// Values of `Set-Cookie` headers, one per cookie
+    set_cookie: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyEncoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
+/// The request as received by the server
+#[derive(Serialize, ToSchema, Debug)]
+pub struct RequestEcho {
+    method: String,
+
+    /// Path and query string
+    url: String,
+
+    /// Query parameters, a name maps to more than one value if it was repeated
+    args: BTreeMap<String, Vec<String>>,
+    headers: BTreeMap<String, Vec<String>>,
+
+    /// Address of the client
+    origin: Option<String>,
+    request_id: Option<String>,
+
+    /// The body, base64 encoded if it is not valid UTF-8
+    body: String,
+    body_encoding: BodyEncoding,
+
+    /// The body parsed as JSON if it was sent as JSON
+    #[schema(value_type = Option<Object>)]
+    json: Option<serde_json::Value>,
+
+    /// The body parsed as a form if it was sent as a URL encoded form
+    form: Option<BTreeMap<String, Vec<String>>>,
+}
+
+impl ResponseSpec {
+    fn from_query(pairs: &[(String, String)]) -> anyhow::Result<Self> {
+        let mut result = Self::default();
+        for (key, value) in pairs {
+            match key.as_str() {
+                "status" => {
+                    result.status = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("invalid status: {value:?}"))?,
+                    )
+                }
+                "header" => result.headers.push(value.clone()),
+                "content_type" => result.content_type = Some(value.clone()),
+                "body" => result.body = Some(value.clone()),
+                "body_encoding" => {
+                    result.body_encoding = match value.as_str() {
+                        "utf8" => BodyEncoding::Utf8,
+                        "base64" => BodyEncoding::Base64,
+                        _ => bail!("body_encoding must be utf8 or base64 but found: {value:?}"),
+                    }
+                }
+                "set_cookie" => result.set_cookie.push(value.clone()),
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+
+    /// Starts the response with the status and headers from the spec
+    fn response_builder(&self) -> anyhow::Result<HttpResponseBuilder> {
+        let status = self.status.unwrap_or(200);
+        let status = StatusCode::from_u16(status)
+            .ok()
+            .filter(|x| !x.is_informational())
+            .with_context(|| format!("status must be between 200 and 999 but found: {status}"))?;
+        let mut builder = HttpResponse::build(status);
+        for header in self.headers.iter() {
+            let (name, value) = header
+                .split_once(':')
+                .with_context(|| format!("header must be `Name: Value` but found: {header:?}"))?;
+            builder.append_header((header_name(name.trim())?, header_value(value.trim())?));
+        }
+        for cookie in self.set_cookie.iter() {
+            builder.append_header((SET_COOKIE, header_value(cookie)?));
+        }
+        if let Some(content_type) = self.content_type.as_deref() {
+            builder.insert_header((CONTENT_TYPE, header_value(content_type)?));
+        }
+        Ok(builder)
+    }
+
+    fn decoded_body(&self) -> anyhow::Result<Vec<u8>> {
+        let body = self.body.as_deref().unwrap_or_default();
+        match self.body_encoding {
+            BodyEncoding::Utf8 => Ok(body.as_bytes().to_vec()),
+            BodyEncoding::Base64 => STANDARD.decode(body).context("body is not valid base64"),
+        }
+    }
+}
+
+impl RequestEcho {
+    fn new(req: &HttpRequest, query: Vec<(String, String)>, body: &Bytes) -> Self {
+        let (body_text, body_encoding) = match std::str::from_utf8(body) {
+            Ok(text) => (text.to_string(), BodyEncoding::Utf8),
+            Err(_) => (STANDARD.encode(body), BodyEncoding::Base64),
+        };
+        let mime = req.mime_type().ok().flatten();
+        let json = mime
+            .as_ref()
+            .filter(|x| x.subtype() == "json" || x.suffix().is_some_and(|x| x == "json"))
+            .and_then(|_| serde_json::from_slice(body).ok());
+        let form = mime
+            .as_ref()
+            .filter(|x| x.essence_str() == "application/x-www-form-urlencoded")
+            .and_then(|_| Query::<Vec<(String, String)>>::from_query(&body_text).ok())
+            .map(|x| group_values(x.into_inner()));
+        Self {
+            method: req.method().to_string(),
+            url: req.uri().to_string(),
+            args: group_values(query),
+            headers: header_values(req.headers()),
+            origin: req.peer_addr().map(|x| x.ip().to_string()),
+            request_id: RequestId::of(req).map(|x| x.to_string()),
+            body: body_text,
+            body_encoding,
+            json,
+            form,
+        }
+    }
+}
+
+fn header_name(name: &str) -> anyhow::Result<HeaderName> {
+    HeaderName::from_bytes(name.as_bytes())
+        .with_context(|| format!("invalid header name: {name:?}"))
+}
+
+fn header_value(value: &str) -> anyhow::Result<HeaderValue> {
+    HeaderValue::from_str(value).with_context(|| format!("invalid header value: {value:?}"))
+}
+
+fn group_values(pairs: Vec<(String, String)>) -> BTreeMap<String, Vec<String>> {
+    let mut result: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for (key, value) in pairs {
+        result.entry(key).or_default().push(value);
+    }
+    result
+}
+
+fn header_values(headers: &HeaderMap) -> BTreeMap<String, Vec<String>> {
+    group_values(
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+                (name.to_string(), value)
+            })
+            .collect(),
+    )
+}
+
+fn bad_request(error: anyhow::Error) -> HandlerError {
+    HandlerError::new(ErrorKind::BadRequest, error)
+}
+
+/// Responds with an echo of the request as JSON using the status and headers from the query
+///
+/// Any path starting with `/anything/` is also accepted.
+#[utoipa::path(
+    method(get, post, put, patch, delete),
+    path = "/anything",
+    tag = "anything",
+    params(
+        ("status" = Option<u16>, Query, description = "Status code from 200 to 999, defaults to 200"),
+        ("header" = Option<Vec<String>>, Query, description = "Header to add as `Name: Value`, may be repeated"),
+        ("content_type" = Option<String>, Query, description = "Content type, defaults to `application/json`"),
+        ("set_cookie" = Option<Vec<String>>, Query, description = "Value of a `Set-Cookie` header, may be repeated"),
+    ),
+    request_body(content = Vec<u8>, description = "Any body", content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "The request that was received", body = RequestEcho),
+        (status = 400, description = "Invalid response description", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn anything(
+    req: HttpRequest,
+    Query(query): Query<Vec<(String, String)>>,
+    body: Bytes,
+) -> crate::Result<HttpResponse> {
+    let spec = ResponseSpec::from_query(&query).map_err(bad_request)?;
+    let echo = RequestEcho::new(&req, query, &body);
+    let body = serde_json::to_string_pretty(&echo).context("failed to serialize echo")?;
+    let mut res = spec.response_builder().map_err(bad_request)?.body(body);
+    if !res.headers().contains_key(CONTENT_TYPE) {
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    }
+    Ok(res)
+}
+
+/// Responds with exactly the response described by the query or a JSON body
+///
+/// The JSON body is used if the request has one, otherwise the query parameters.
+#[utoipa::path(
+    method(get, post, put, patch, delete),
+    path = "/response",
+    tag = "anything",
+    params(
+        ("status" = Option<u16>, Query, description = "Status code from 200 to 999, defaults to 200"),
+        ("header" = Option<Vec<String>>, Query, description = "Header to add as `Name: Value`, may be repeated"),
+        ("content_type" = Option<String>, Query, description = "Content type, none if not set"),
+        ("body" = Option<String>, Query, description = "Body of the response"),
+        ("body_encoding" = Option<BodyEncoding>, Query, description = "How `body` is encoded, defaults to `utf8`"),
+        ("set_cookie" = Option<Vec<String>>, Query, description = "Value of a `Set-Cookie` header, may be repeated"),
+    ),
+    request_body(content = Option<ResponseSpec>, description = "Used instead of the query parameters"),
+    responses(
+        (status = 200, description = "The response described, other status codes are possible"),
+        (status = 400, description = "Invalid response description", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn response(
+    Query(query): Query<Vec<(String, String)>>,
+    body: Bytes,
+) -> crate::Result<HttpResponse> {
+    let spec = if body.is_empty() {
+        ResponseSpec::from_query(&query)
+    } else {
+        serde_json::from_slice(&body).context("failed to parse response description")
+    }
+    .map_err(bad_request)?;
+    let body = spec.decoded_body().map_err(bad_request)?;
+    Ok(spec.response_builder().map_err(bad_request)?.body(body))
+}
+
+/// Adds each query parameter as a response header and returns them as JSON
+#[utoipa::path(
+    get,
+    path = "/response-headers",
+    tag = "anything",
+    params(("params" = BTreeMap<String, String>, Query, style = Form, explode, description = "Headers to add")),
+    responses(
+        (status = 200, description = "The headers added", body = BTreeMap<String, Vec<String>>),
+        (status = 400, description = "Invalid header", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn response_headers(
+    Query(query): Query<Vec<(String, String)>>,
+) -> crate::Result<HttpResponse> {
+    let mut builder = HttpResponse::Ok();
+    for (name, value) in query.iter() {
+        builder.append_header((
+            header_name(name).map_err(bad_request)?,
+            header_value(value).map_err(bad_request)?,
+        ));
+    }
+    Ok(builder.json(group_values(query)))
+}
@@ -0,0 +1,229 @@
+//! Based on https://httpbin.org/#/Dynamic_data
+use actix_web::{
+    http::header::{
+        self, ContentRange, ContentRangeSpec, ContentType, EntityTag, Header as _, IfRange, Range,
+    },
+    web::{Bytes, Path, Query},
+    HttpRequest, HttpResponse,
+};
+use anyhow::anyhow;
+use futures_util::stream;
+use serde::Deserialize;
+use tracing::instrument;
+use utoipa::IntoParams;
+
+use crate::{ErrorKind, HandlerError, ProblemDetails};
+
+/// Largest number of bytes any of these endpoints will produce
+const MAX_BYTES: usize = 100 * 1024;
+
+/// Default chunk size for `/stream-bytes/{n}`
+const DEFAULT_CHUNK_SIZE: usize = 10 * 1024;
+
+/// Boundary used to separate the parts of a `multipart/byteranges` response
+const MULTIPART_BOUNDARY: &str = "3d6b6a416f9b5";
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BytesQuery {
+    /// Seed for the generator, random if not provided
+    seed: Option<u64>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamBytesQuery {
+    /// Seed for the generator, random if not provided
+    seed: Option<u64>,
+    /// Number of bytes per chunk, defaults to 10 KiB
+    chunk_size: Option<usize>,
+}
+
+/// Deterministic pseudo random generator (splitmix64) so the same seed always
+/// produces the same bytes regardless of platform or dependency versions
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn new(seed: Option<u64>) -> Self {
+        Self(seed.unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|x| x.as_nanos() as u64)
+                .unwrap_or_default()
+        }))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let value = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&value[..chunk.len()]);
+        }
+    }
+}
+
+fn check_size(n: usize) -> crate::Result<()> {
+    if n > MAX_BYTES {
+        return Err(HandlerError::new(
+            ErrorKind::BadRequest,
+            anyhow!("at most {MAX_BYTES} bytes may be requested"),
+        ));
+    }
+    Ok(())
+}
+
+/// The content served by `/range/{n}`, the lowercase alphabet repeated to fill `n` bytes
+fn range_content(n: usize) -> Vec<u8> {
+    (b'a'..=b'z').cycle().take(n).collect()
+}
+
+/// Returns `n` bytes of the alphabet honouring `Range` and `If-Range` request headers
+#[utoipa::path(
+    get,
+    path = "/range/{n}",
+    tag = "bytes",
+    params(("n" = usize, Path, description = "Number of bytes, at most 102400")),
+    responses(
+        (status = 200, description = "All the bytes", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 206, description = "The requested range(s), multiple ranges use `multipart/byteranges`", body = Vec<u8>),
+        (status = 400, description = "Too many bytes requested", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 416, description = "None of the requested ranges can be satisfied"),
+    )
+)]
+#[instrument]
+pub async fn range(req: HttpRequest, path: Path<usize>) -> crate::Result<HttpResponse> {
+    let n = path.into_inner();
+    check_size(n)?;
+    let content = range_content(n);
+    let etag = EntityTag::new_strong(format!("range{n}"));
+
+    let mut builder = HttpResponse::Ok();
+    builder
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header(header::ETag(etag.clone()));
+
+    // A malformed or non-byte `Range` header is ignored and the full content returned
+    let Ok(Range::Bytes(specs)) = Range::parse(&req) else {
+        return Ok(builder
+            .content_type(ContentType::octet_stream())
+            .body(content));
+    };
+
+    // `If-Range` only allows a partial response when the validator still matches
+    // the representation. No `Last-Modified` is sent so dates never match.
+    let if_range_matches = !req.headers().contains_key(header::IF_RANGE)
+        || matches!(IfRange::parse(&req), Ok(IfRange::EntityTag(tag)) if tag.strong_eq(&etag));
+    if !if_range_matches {
+        return Ok(builder
+            .content_type(ContentType::octet_stream())
+            .body(content));
+    }
+
+    let ranges: Vec<(u64, u64)> = specs
+        .iter()
+        .filter_map(|spec| spec.to_satisfiable_range(n as u64))
+        .collect();
+
+    let response = match ranges.as_slice() {
+        [] => HttpResponse::RangeNotSatisfiable()
+            .insert_header((header::ACCEPT_RANGES, "bytes"))
+            .insert_header(ContentRange(ContentRangeSpec::Bytes {
+                range: None,
+                instance_length: Some(n as u64),
+            }))
+            .finish(),
+        [(start, end)] => builder
+            .status(actix_web::http::StatusCode::PARTIAL_CONTENT)
+            .insert_header(ContentRange(ContentRangeSpec::Bytes {
+                range: Some((*start, *end)),
+                instance_length: Some(n as u64),
+            }))
+            .content_type(ContentType::octet_stream())
+            .body(content[*start as usize..=*end as usize].to_vec()),
+        ranges => {
+            let mut body = Vec::new();
+            for (start, end) in ranges {
+                body.extend_from_slice(
+                    format!(
+                        "\r\n--{MULTIPART_BOUNDARY}\r\n\
+                         Content-Type: application/octet-stream\r\n\
+                         Content-Range: bytes {start}-{end}/{n}\r\n\r\n"
+                    )
+                    .as_bytes(),
+                );
+                body.extend_from_slice(&content[*start as usize..=*end as usize]);
+            }
+            body.extend_from_slice(format!("\r\n--{MULTIPART_BOUNDARY}--\r\n").as_bytes());
+            builder
+                .status(actix_web::http::StatusCode::PARTIAL_CONTENT)
+                .insert_header((
+                    header::CONTENT_TYPE,
+                    format!("multipart/byteranges; boundary={MULTIPART_BOUNDARY}"),
+                ))
+                .body(body)
+        }
+    };
+    Ok(response)
+}
+
+/// Returns `n` pseudo random bytes, the same `seed` always produces the same bytes
+#[utoipa::path(
+    get,
+    path = "/bytes/{n}",
+    tag = "bytes",
+    params(("n" = usize, Path, description = "Number of bytes, at most 102400"), BytesQuery),
+    responses(
+        (status = 200, description = "The generated bytes", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 400, description = "Too many bytes requested", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn random_bytes(
+    path: Path<usize>,
+    Query(BytesQuery { seed }): Query<BytesQuery>,
+) -> crate::Result<HttpResponse> {
+    let n = path.into_inner();
+    check_size(n)?;
+    let mut body = vec![0; n];
+    SplitMix64::new(seed).fill(&mut body);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::octet_stream())
+        .body(body))
+}
+
+/// Streams the same bytes as `/bytes/{n}` using chunked transfer encoding
+#[utoipa::path(
+    get,
+    path = "/stream-bytes/{n}",
+    tag = "bytes",
+    params(("n" = usize, Path, description = "Number of bytes, at most 102400"), StreamBytesQuery),
+    responses(
+        (status = 200, description = "The generated bytes sent in chunks", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 400, description = "Too many bytes requested", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn stream_bytes(
+    path: Path<usize>,
+    Query(StreamBytesQuery { seed, chunk_size }): Query<StreamBytesQuery>,
+) -> crate::Result<HttpResponse> {
+    let n = path.into_inner();
+    check_size(n)?;
+    let chunk_size = chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE).max(1);
+    let mut data = vec![0; n];
+    SplitMix64::new(seed).fill(&mut data);
+    let data = Bytes::from(data);
+    let chunks = (0..n).step_by(chunk_size).map(move |start| {
+        Ok::<_, actix_web::Error>(data.slice(start..(start + chunk_size).min(n)))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::octet_stream())
+        .streaming(stream::iter(chunks)))
+}
@@ -0,0 +1,292 @@
+//! Sets cookies that clients are known to handle differently
+//!
+//! The `Set-Cookie` headers are written exactly as listed instead of being built
+//! with [`actix_web::cookie::Cookie`] which would normalize them.
+use actix_web::{
+    http::header::{HeaderValue, SET_COOKIE},
+    web::{Json, Path, Query},
+    HttpRequest, HttpResponse,
+};
+use anyhow::{anyhow, Context as _};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::{IntoParams, ToSchema};
+
+use super::cookies::request_host;
+use crate::{ErrorKind, HandlerError, ProblemDetails};
+
+/// Number of cookies set by `many` if not specified
+const DEFAULT_COUNT: usize = 50;
+
+/// Largest number of cookies `many` will set
+const MAX_COUNT: usize = 1000;
+
+/// Browsers reject cookies where the name and value together are longer than this
+const SIZE_LIMIT: usize = 4096;
+
+const EPOCH: &str = "Thu, 01 Jan 1970 00:00:00 GMT";
+const FAR_FUTURE: &str = "Fri, 31 Dec 9999 23:59:59 GMT";
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct StressQuery {
+    /// Number of cookies set by `many`, defaults to 50 and ignored by other variants
+    count: Option<usize>,
+}
+
+struct Variant {
+    name: &'static str,
+    description: &'static str,
+}
+
+const VARIANTS: &[Variant] = &[
+    Variant {
+        name: "many",
+        description: "Sets `count` cookies at once, browsers keep at most 180 per domain and some clients limit the number of response headers",
+    },
+    Variant {
+        name: "large",
+        description: "Sets cookies just under, at and over the 4096 byte limit for the name and value together",
+    },
+    Variant {
+        name: "duplicates",
+        description: "Sets cookies with the same name on different paths and domains",
+    },
+    Variant {
+        name: "quoted",
+        description: "Sets cookies with quoted values, unusual characters and odd names",
+    },
+    Variant {
+        name: "expiry",
+        description: "Sets cookies with conflicting, repeated and invalid `Expires` and `Max-Age` attributes",
+    },
+];
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct VariantInfo {
+    name: &'static str,
+    description: &'static str,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct StressCookie {
+    /// Value of the `Set-Cookie` header exactly as sent
+    set_cookie: String,
+
+    /// What a client following RFC 6265 and browser behaviour should do with it
+    expected: &'static str,
+}
+
+impl StressCookie {
+    fn new(set_cookie: impl Into<String>, expected: &'static str) -> Self {
+        Self {
+            set_cookie: set_cookie.into(),
+            expected,
+        }
+    }
+}
+
+fn many(count: usize) -> Vec<StressCookie> {
+    (0..count)
+        .map(|i| StressCookie::new(format!("stress_{i:04}=value_{i}; Path=/"), "stored"))
+        .collect()
+}
+
+fn large() -> Vec<StressCookie> {
+    [SIZE_LIMIT - 96, SIZE_LIMIT - 1, SIZE_LIMIT, SIZE_LIMIT + 1]
+        .into_iter()
+        .map(|size| {
+            let name = format!("large_{size}");
+            let value = "x".repeat(size - name.len());
+            let expected = if size <= SIZE_LIMIT {
+                "stored"
+            } else {
+                "rejected, the name and value together are longer than 4096 bytes"
+            };
+            StressCookie::new(format!("{name}={value}; Path=/"), expected)
+        })
+        .collect()
+}
+
+/// `host` is used for the `Domain` attribute
+fn duplicates(host: &str) -> Vec<StressCookie> {
+    vec![
+        StressCookie::new("dup=root; Path=/", "replaced by `dup=last`"),
+        StressCookie::new(
+            "dup=cookies; Path=/cookies",
+            "stored, sent before the `/` cookies to paths under `/cookies` as longer paths come first",
+        ),
+        StressCookie::new(
+            "dup=stress; Path=/cookies/stress",
+            "stored, sent first to paths under `/cookies/stress`",
+        ),
+        StressCookie::new(
+            format!("dup=domain; Path=/; Domain={host}"),
+            "stored separately from the host only cookie, browsers do not allow IP addresses as a domain",
+        ),
+        StressCookie::new(
+            "dup=other_domain; Path=/; Domain=example.com",
+            "rejected, the domain does not match the host",
+        ),
+        StressCookie::new(
+            "dup=last; Path=/",
+            "stored, replaces `dup=root` as the name, path and domain are the same",
+        ),
+    ]
+}
+
+fn quoted() -> Vec<StressCookie> {
+    vec![
+        StressCookie::new(
+            r#"quoted="hello world"; Path=/"#,
+            "stored with the quotes as part of the value, some clients remove them",
+        ),
+        StressCookie::new(
+            r#"quoted_empty=""; Path=/"#,
+            r#"stored with the value `""`"#,
+        ),
+        StressCookie::new(
+            r#"quoted_semicolon="a;b"; Path=/"#,
+            r#"stored with the value `"a` as the value ends at the first semicolon"#,
+        ),
+        StressCookie::new(
+            "special=!#$%&'()*+-./:<>?@[]^_`{|}~; Path=/",
+            "stored unchanged, these are all allowed in values",
+        ),
+        StressCookie::new(
+            "comma=a,b; Path=/",
+            "stored by browsers, RFC 6265 does not allow commas in values",
+        ),
+        StressCookie::new(
+            "space=a b; Path=/",
+            "stored by browsers, RFC 6265 does not allow spaces in values",
+        ),
+        StressCookie::new(
+            "equals=a=b=c; Path=/",
+            "stored with the value `a=b=c` as only the first `=` ends the name",
+        ),
+        StressCookie::new(
+            "percent=caf%C3%A9; Path=/",
+            "stored as is, cookie values are not percent decoded",
+        ),
+        StressCookie::new(
+            "utf8=café; Path=/",
+            "stored by browsers, RFC 6265 only allows ASCII",
+        ),
+        StressCookie::new(
+            "=nameless; Path=/",
+            "stored by browsers with an empty name, rejected by some clients",
+        ),
+        StressCookie::new(
+            "padded  =  value  ; Path=/",
+            "stored as `padded=value`, whitespace around the name and value is removed",
+        ),
+    ]
+}
+
+fn expiry() -> Vec<StressCookie> {
+    vec![
+        StressCookie::new(
+            format!("max_age_wins=1; Path=/; Max-Age=3600; Expires={EPOCH}"),
+            "stored for an hour, `Max-Age` takes precedence over `Expires`",
+        ),
+        StressCookie::new(
+            format!("max_age_zero=1; Path=/; Max-Age=0; Expires={FAR_FUTURE}"),
+            "removed, `Max-Age` takes precedence over `Expires`",
+        ),
+        StressCookie::new(
+            "max_age_negative=1; Path=/; Max-Age=-1",
+            "removed, a negative `Max-Age` expires immediately",
+        ),
+        StressCookie::new(
+            format!("expires_past=1; Path=/; Expires={EPOCH}"),
+            "removed, it has already expired",
+        ),
+        StressCookie::new(
+            format!("expires_twice=1; Path=/; Expires={EPOCH}; Expires={FAR_FUTURE}"),
+            "stored, the last `Expires` is used",
+        ),
+        StressCookie::new(
+            "expires_invalid=1; Path=/; Expires=not a date",
+            "stored as a session cookie, the invalid `Expires` is ignored",
+        ),
+        StressCookie::new(
+            "max_age_invalid=1; Path=/; Max-Age=soon",
+            "stored as a session cookie, the invalid `Max-Age` is ignored",
+        ),
+    ]
+}
+
+/// Lists the variants available at `/cookies/stress/{variant}`
+#[utoipa::path(
+    get,
+    path = "/cookies/stress",
+    tag = "cookies",
+    responses((status = 200, description = "Available variants", body = Vec<VariantInfo>))
+)]
+#[instrument]
+pub async fn cookie_stress_list() -> Json<Vec<VariantInfo>> {
+    Json(
+        VARIANTS
+            .iter()
+            .map(|x| VariantInfo {
+                name: x.name,
+                description: x.description,
+            })
+            .collect(),
+    )
+}
+
+/// Sets the cookies for a variant and lists them with the expected outcome of each
+#[utoipa::path(
+    get,
+    path = "/cookies/stress/{variant}",
+    tag = "cookies",
+    params(
+        ("variant" = String, Path, description = "Name of the variant as listed by `/cookies/stress`"),
+        StressQuery,
+    ),
+    responses(
+        (status = 200, description = "Cookies set, in the order of the `Set-Cookie` headers", body = Vec<StressCookie>),
+        (status = 400, description = "Too many cookies requested", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No variant with that name", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn cookie_stress(
+    req: HttpRequest,
+    path: Path<String>,
+    Query(query): Query<StressQuery>,
+) -> crate::Result<HttpResponse> {
+    let variant = path.into_inner();
+    let cookies = match variant.as_str() {
+        "many" => {
+            let count = query.count.unwrap_or(DEFAULT_COUNT);
+            if count > MAX_COUNT {
+                return Err(HandlerError::new(
+                    ErrorKind::BadRequest,
+                    anyhow!("count may be at most {MAX_COUNT}"),
+                ));
+            }
+            many(count)
+        }
+        "large" => large(),
+        "duplicates" => duplicates(&request_host(&req)),
+        "quoted" => quoted(),
+        "expiry" => expiry(),
+        _ => {
+            return Err(HandlerError::new(
+                ErrorKind::NotFound,
+                anyhow!("no cookie stress variant named: {variant}"),
+            ))
+        }
+    };
+    let mut builder = HttpResponse::Ok();
+    for cookie in cookies.iter() {
+        // Built from bytes so values with characters outside ASCII are sent unchanged
+        let value = HeaderValue::from_bytes(cookie.set_cookie.as_bytes())
+            .with_context(|| format!("invalid Set-Cookie value: {:?}", cookie.set_cookie))?;
+        builder.append_header((SET_COOKIE, value));
+    }
+    Ok(builder.json(cookies))
+}
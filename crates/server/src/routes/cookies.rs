@@ -8,22 +8,66 @@ use actix_web::{
 use anyhow::Context;
 use serde::Deserialize;
 use tracing::instrument;
+use utoipa::IntoParams;
 
-#[derive(Deserialize)]
+use crate::{ErrorKind, HandlerError, ProblemDetails};
+
+/// The host the client sent the request to without the port, used to scope cookies to a domain
+pub(super) fn request_host(req: &HttpRequest) -> String {
+    let info = req.connection_info();
+    let host = info.host();
+    // IPv6 addresses are in brackets and contain colons
+    match host.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => host.to_string(),
+        _ => host.to_string(),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct QueryData {
+    /// If present respond directly instead of redirecting to `/cookies/`
     stay: Option<String>,
 }
 
+/// Lists the cookies sent with the request as name value pairs
+#[utoipa::path(
+    get,
+    path = "/cookies/",
+    tag = "cookies",
+    responses(
+        (status = 200, description = "Cookies received", body = Vec<(String, String)>),
+        (status = 400, description = "Cookies could not be parsed", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
 #[instrument]
 pub async fn cookie_show(req: HttpRequest) -> crate::Result<Json<Vec<(String, String)>>> {
     let mut result = vec![];
-    let cookies = req.cookies().context("failed to access list of cookies")?;
+    let cookies = req
+        .cookies()
+        .context("failed to access list of cookies")
+        .map_err(|e| HandlerError::new(ErrorKind::InvalidCookies, e))?;
     for cookie in cookies.iter() {
         result.push((cookie.name().to_string(), cookie.value().to_string()));
     }
     Ok(Json(result))
 }
 
+/// Sets a cookie then redirects to `/cookies/` unless `stay` is present
+#[utoipa::path(
+    get,
+    path = "/cookies/set/{name}/{value}",
+    tag = "cookies",
+    params(
+        ("name" = String, Path, description = "Name of the cookie"),
+        ("value" = String, Path, description = "Value of the cookie"),
+        QueryData,
+    ),
+    responses(
+        (status = 303, description = "Cookie set, redirecting to `/cookies/`"),
+        (status = 200, description = "Cookie set", body = String),
+    )
+)]
 #[instrument]
 pub async fn cookie_set(
     path: Path<(String, String)>,
@@ -43,6 +87,20 @@ pub async fn cookie_set(
     }
 }
 
+/// Removes a cookie then redirects to `/cookies/` unless `stay` is present
+#[utoipa::path(
+    get,
+    path = "/cookies/delete/{name}",
+    tag = "cookies",
+    params(
+        ("name" = String, Path, description = "Name of the cookie"),
+        QueryData,
+    ),
+    responses(
+        (status = 303, description = "Cookie removed, redirecting to `/cookies/`"),
+        (status = 200, description = "Cookie removed", body = String),
+    )
+)]
 #[instrument]
 pub async fn cookie_expire(
     path: Path<String>,
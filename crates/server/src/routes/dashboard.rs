@@ -0,0 +1,283 @@
+//! Page showing what the server received, for those not running the client app
+//!
+//! The tables are rendered on the server and kept up to date by a small inline script
+//! listening to the `snapshot` events sent by `/_dashboard/events`.
+use std::{convert::Infallible, fmt::Write as _, time::Duration};
+
+use actix_web::{
+    http::header::{CACHE_CONTROL, CONTENT_TYPE},
+    rt::time::{sleep, timeout},
+    web::{Bytes, Data},
+    HttpResponse,
+};
+use anyhow::Context as _;
+use futures_util::stream;
+use serde::Serialize;
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use super::{rate_limit::BucketStatus, RateLimitBuckets};
+use crate::{
+    proxy::{CaptureSummary, Proxy},
+    recent_requests::{RecentRequest, RecentRequests},
+};
+
+/// Longest time between snapshots, the bucket levels change even without new requests
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Wait after a new request before sending a snapshot so bursts are sent together
+const BATCH_DELAY: Duration = Duration::from_millis(200);
+
+/// Columns of a table as the snapshot field and the heading
+struct Section {
+    field: &'static str,
+    title: &'static str,
+    columns: &'static [(&'static str, &'static str)],
+}
+
+const SECTIONS: &[Section] = &[
+    Section {
+        field: "requests",
+        title: "Recent requests",
+        columns: &[
+            ("started_at_ms", "Time (UTC)"),
+            ("method", "Method"),
+            ("path", "Path"),
+            ("version", "Version"),
+            ("status", "Status"),
+            ("duration_ms", "ms"),
+            ("client", "Client"),
+            ("request_id", "Request ID"),
+        ],
+    },
+    Section {
+        field: "rate_limit_buckets",
+        title: "Rate limit buckets",
+        columns: &[
+            ("bucket", "Bucket"),
+            ("limit", "Limit"),
+            ("remaining", "Remaining"),
+            ("reset", "Full in (s)"),
+            ("retry_after", "Retry after (s)"),
+        ],
+    },
+    Section {
+        field: "proxy_captures",
+        title: "Proxy captures",
+        columns: &[
+            ("started_at_ms", "Time (UTC)"),
+            ("mode", "Mode"),
+            ("method", "Method"),
+            ("url", "URL"),
+            ("status", "Status"),
+            ("duration_ms", "ms"),
+            ("error", "Error"),
+        ],
+    },
+];
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct DashboardSnapshot {
+    /// Newest first, requests to the dashboard are not included
+    requests: Vec<RecentRequest>,
+    rate_limit_buckets: Vec<BucketStatus>,
+
+    /// Newest first, see `/_proxy/captures` for the headers and bodies
+    proxy_captures: Vec<CaptureSummary>,
+}
+
+impl DashboardSnapshot {
+    fn new(recent: &RecentRequests, buckets: &RateLimitBuckets, proxy: &Proxy) -> Self {
+        Self {
+            requests: recent.list(),
+            rate_limit_buckets: buckets.statuses(),
+            proxy_captures: proxy.captures.summaries(),
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Same as `format` in [`SCRIPT`]
+fn format_cell(key: &str, value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Number(ms) if key.ends_with("_at_ms") => {
+            let ms = ms.as_u64().unwrap_or_default() % 86_400_000;
+            format!(
+                "{:02}:{:02}:{:02}.{:03}",
+                ms / 3_600_000,
+                ms / 60_000 % 60,
+                ms / 1000 % 60,
+                ms % 1000
+            )
+        }
+        other => other.to_string(),
+    }
+}
+
+fn render_page(snapshot: &DashboardSnapshot) -> anyhow::Result<String> {
+    let snapshot = serde_json::to_value(snapshot).context("failed to convert snapshot to JSON")?;
+    let mut body = String::new();
+    for section in SECTIONS {
+        let rows = snapshot[section.field]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        write!(
+            body,
+            "<h2>{}</h2>\n<table data-section=\"{}\">\n<thead><tr>",
+            section.title, section.field
+        )?;
+        for (key, heading) in section.columns {
+            write!(body, "<th data-key=\"{key}\">{heading}</th>")?;
+        }
+        body.push_str("</tr></thead>\n<tbody>\n");
+        if rows.is_empty() {
+            writeln!(
+                body,
+                "<tr><td colspan=\"{}\">None yet</td></tr>",
+                section.columns.len()
+            )?;
+        }
+        for row in rows.iter() {
+            body.push_str("<tr>");
+            for (key, _) in section.columns {
+                write!(
+                    body,
+                    "<td>{}</td>",
+                    escape_html(&format_cell(key, &row[key]))
+                )?;
+            }
+            body.push_str("</tr>\n");
+        }
+        body.push_str("</tbody>\n</table>\n");
+    }
+    Ok(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>HTTP Test Server Dashboard</title>
+<style>{STYLE}</style>
+</head>
+<body>
+<h1>HTTP Test Server Dashboard <small id="status">Static</small></h1>
+{body}<script>{SCRIPT}</script>
+</body>
+</html>
+"#
+    ))
+}
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 1em 2em; }
+small { font-size: 0.5em; color: #666; }
+table { border-collapse: collapse; margin-bottom: 2em; font-size: 0.9em; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.5em; text-align: left; }
+th { background: #eee; }
+td { font-family: monospace; word-break: break-all; }
+";
+
+const SCRIPT: &str = r#"
+const status = document.getElementById("status");
+function format(key, value) {
+  if (value === null || value === undefined) return "";
+  if (key.endsWith("_at_ms")) return new Date(value).toISOString().slice(11, 23);
+  if (typeof value === "object") return JSON.stringify(value);
+  return String(value);
+}
+function cell(text, columns) {
+  const td = document.createElement("td");
+  td.textContent = text;
+  if (columns) td.colSpan = columns;
+  return td;
+}
+const source = new EventSource("/_dashboard/events");
+source.addEventListener("snapshot", (event) => {
+  const snapshot = JSON.parse(event.data);
+  for (const table of document.querySelectorAll("table[data-section]")) {
+    const keys = [...table.querySelectorAll("th")].map((th) => th.dataset.key);
+    const rows = snapshot[table.dataset.section].map((row) => {
+      const tr = document.createElement("tr");
+      tr.append(...keys.map((key) => cell(format(key, row[key]))));
+      return tr;
+    });
+    if (rows.length === 0) {
+      const tr = document.createElement("tr");
+      tr.append(cell("None yet", keys.length));
+      rows.push(tr);
+    }
+    table.tBodies[0].replaceChildren(...rows);
+  }
+  status.textContent = "Live";
+});
+source.addEventListener("error", () => (status.textContent = "Reconnecting"));
+"#;
+
+/// Page listing recent requests, rate limit buckets and proxy captures, updated live
+#[utoipa::path(
+    get,
+    path = "/_dashboard",
+    tag = "dashboard",
+    responses((status = 200, description = "HTML page", body = String, content_type = "text/html"))
+)]
+#[instrument]
+pub async fn dashboard_page(
+    recent: Data<RecentRequests>,
+    buckets: Data<RateLimitBuckets>,
+    proxy: Data<Proxy>,
+) -> crate::Result<HttpResponse> {
+    let html = render_page(&DashboardSnapshot::new(&recent, &buckets, &proxy))?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html))
+}
+
+/// Server-sent events with a `snapshot` of everything on the dashboard after each change
+///
+/// The first snapshot is sent immediately, then after new requests and at least every
+/// two seconds. The stream does not end by itself, a stopping server closes it once its
+/// shutdown timeout has passed.
+#[utoipa::path(
+    get,
+    path = "/_dashboard/events",
+    tag = "dashboard",
+    responses((status = 200, description = "`snapshot` events with a `DashboardSnapshot` as the data", body = DashboardSnapshot, content_type = "text/event-stream"))
+)]
+#[instrument]
+pub async fn dashboard_events(
+    recent: Data<RecentRequests>,
+    buckets: Data<RateLimitBuckets>,
+    proxy: Data<Proxy>,
+) -> HttpResponse {
+    let changes = recent.subscribe();
+    let events = stream::unfold((true, changes), move |(first, mut changes)| {
+        let (recent, buckets, proxy) = (recent.clone(), buckets.clone(), proxy.clone());
+        async move {
+            if !first {
+                match timeout(REFRESH_INTERVAL, changes.changed()).await {
+                    Ok(Ok(())) => sleep(BATCH_DELAY).await,
+                    // The server is shutting down
+                    Ok(Err(_)) => return None,
+                    Err(_) => {}
+                }
+            }
+            let snapshot = DashboardSnapshot::new(&recent, &buckets, &proxy);
+            let data = serde_json::to_string(&snapshot).ok()?;
+            let event = Bytes::from(format!("event: snapshot\ndata: {data}\n\n"));
+            Some((Ok::<_, Infallible>(event), (false, changes)))
+        }
+    });
+    HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "text/event-stream"))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(events)
+}
@@ -0,0 +1,334 @@
+//! The same multilingual text in several character encodings to test how clients decode bodies
+//!
+//! Characters that an encoding cannot represent are replaced with HTML numeric
+//! character references (for example `&#128640;`) the same way browsers encode forms.
+use actix_web::{
+    http::header::CONTENT_TYPE,
+    web::{Json, Path},
+    HttpResponse,
+};
+use anyhow::anyhow;
+use serde::Serialize;
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::{ErrorKind, HandlerError, ProblemDetails};
+
+/// Text served by every variant, chosen so each encoding is missing some characters
+const TEXT: &str = "\
+English: The quick brown fox jumps over the lazy dog.
+Deutsch: Grüße aus Köln, Fußgänger
+Français: café, naïve, œuvre, « guillemets »
+Español: ¿Dónde está el niño? ¡Olé!
+Symbols: € £ ¥ © ® ™ – — “double” ‘single’ …
+Ελληνικά: Καλημέρα κόσμε
+Русский: Привет, мир
+日本語: こんにちは世界、カタカナ、漢字
+中文: 你好，世界
+한국어: 안녕하세요
+Emoji: 🚀🎉
+";
+
+#[derive(Debug, Clone, Copy)]
+enum Charset {
+    Utf8,
+    Utf16 {
+        big_endian: bool,
+        bom: bool,
+    },
+    /// ISO-8859-1 proper, `encoding_rs` treats it as Windows-1252
+    Latin1,
+    Windows1252,
+    ShiftJis,
+    Gb18030,
+}
+
+/// How the `charset` parameter of the `Content-Type` relates to the body
+#[derive(Serialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Label {
+    Correct,
+    Missing,
+    Wrong,
+}
+
+struct Variant {
+    name: &'static str,
+    charset: Charset,
+    content_type: &'static str,
+    label: Label,
+}
+
+const fn variant(
+    name: &'static str,
+    charset: Charset,
+    content_type: &'static str,
+    label: Label,
+) -> Variant {
+    Variant {
+        name,
+        charset,
+        content_type,
+        label,
+    }
+}
+
+const UTF16_LE: Charset = Charset::Utf16 {
+    big_endian: false,
+    bom: false,
+};
+const UTF16_BE: Charset = Charset::Utf16 {
+    big_endian: true,
+    bom: false,
+};
+const UTF16_LE_BOM: Charset = Charset::Utf16 {
+    big_endian: false,
+    bom: true,
+};
+const UTF16_BE_BOM: Charset = Charset::Utf16 {
+    big_endian: true,
+    bom: true,
+};
+
+const VARIANTS: &[Variant] = &[
+    variant(
+        "utf-8",
+        Charset::Utf8,
+        "text/plain; charset=utf-8",
+        Label::Correct,
+    ),
+    variant(
+        "utf-8-no-charset",
+        Charset::Utf8,
+        "text/plain",
+        Label::Missing,
+    ),
+    variant(
+        "utf-8-as-iso-8859-1",
+        Charset::Utf8,
+        "text/plain; charset=iso-8859-1",
+        Label::Wrong,
+    ),
+    variant(
+        "utf-16le-bom",
+        UTF16_LE_BOM,
+        "text/plain; charset=utf-16",
+        Label::Correct,
+    ),
+    variant(
+        "utf-16be-bom",
+        UTF16_BE_BOM,
+        "text/plain; charset=utf-16",
+        Label::Correct,
+    ),
+    variant(
+        "utf-16le",
+        UTF16_LE,
+        "text/plain; charset=utf-16le",
+        Label::Correct,
+    ),
+    variant(
+        "utf-16be",
+        UTF16_BE,
+        "text/plain; charset=utf-16be",
+        Label::Correct,
+    ),
+    variant(
+        "utf-16le-no-charset",
+        UTF16_LE,
+        "text/plain",
+        Label::Missing,
+    ),
+    variant(
+        "utf-16be-as-utf-16le",
+        UTF16_BE,
+        "text/plain; charset=utf-16le",
+        Label::Wrong,
+    ),
+    variant(
+        "iso-8859-1",
+        Charset::Latin1,
+        "text/plain; charset=iso-8859-1",
+        Label::Correct,
+    ),
+    variant(
+        "iso-8859-1-no-charset",
+        Charset::Latin1,
+        "text/plain",
+        Label::Missing,
+    ),
+    variant(
+        "windows-1252",
+        Charset::Windows1252,
+        "text/plain; charset=windows-1252",
+        Label::Correct,
+    ),
+    variant(
+        "windows-1252-as-utf-8",
+        Charset::Windows1252,
+        "text/plain; charset=utf-8",
+        Label::Wrong,
+    ),
+    variant(
+        "shift_jis",
+        Charset::ShiftJis,
+        "text/plain; charset=shift_jis",
+        Label::Correct,
+    ),
+    variant(
+        "shift_jis-no-charset",
+        Charset::ShiftJis,
+        "text/plain",
+        Label::Missing,
+    ),
+    variant(
+        "shift_jis-as-utf-8",
+        Charset::ShiftJis,
+        "text/plain; charset=utf-8",
+        Label::Wrong,
+    ),
+    variant(
+        "gb18030",
+        Charset::Gb18030,
+        "text/plain; charset=gb18030",
+        Label::Correct,
+    ),
+    variant(
+        "gb18030-as-shift_jis",
+        Charset::Gb18030,
+        "text/plain; charset=shift_jis",
+        Label::Wrong,
+    ),
+];
+
+impl Charset {
+    /// Name of the encoding actually used for the body
+    fn name(&self) -> &'static str {
+        match self {
+            Charset::Utf8 => "UTF-8",
+            Charset::Utf16 {
+                big_endian: false, ..
+            } => "UTF-16LE",
+            Charset::Utf16 {
+                big_endian: true, ..
+            } => "UTF-16BE",
+            Charset::Latin1 => "ISO-8859-1",
+            Charset::Windows1252 => "windows-1252",
+            Charset::ShiftJis => "Shift_JIS",
+            Charset::Gb18030 => "GB18030",
+        }
+    }
+
+    /// Used to decode the body, the Windows-1252 decoder is used for ISO-8859-1 which is
+    /// equivalent as the text has no characters in the range where they differ
+    fn decoder(&self) -> &'static encoding_rs::Encoding {
+        match self {
+            Charset::Utf8 => encoding_rs::UTF_8,
+            Charset::Utf16 {
+                big_endian: false, ..
+            } => encoding_rs::UTF_16LE,
+            Charset::Utf16 {
+                big_endian: true, ..
+            } => encoding_rs::UTF_16BE,
+            Charset::Latin1 | Charset::Windows1252 => encoding_rs::WINDOWS_1252,
+            Charset::ShiftJis => encoding_rs::SHIFT_JIS,
+            Charset::Gb18030 => encoding_rs::GB18030,
+        }
+    }
+
+    fn encode(&self, text: &str) -> Vec<u8> {
+        match *self {
+            Charset::Utf8 => text.as_bytes().to_vec(),
+            // Not supported by `encoding_rs` which only decodes UTF-16
+            Charset::Utf16 { big_endian, bom } => {
+                let units = bom.then_some(0xFEFF).into_iter().chain(text.encode_utf16());
+                if big_endian {
+                    units.flat_map(u16::to_be_bytes).collect()
+                } else {
+                    units.flat_map(u16::to_le_bytes).collect()
+                }
+            }
+            Charset::Latin1 => text
+                .chars()
+                .flat_map(|c| match u8::try_from(c) {
+                    Ok(byte) => vec![byte],
+                    Err(_) => format!("&#{};", c as u32).into_bytes(),
+                })
+                .collect(),
+            Charset::Windows1252 | Charset::ShiftJis | Charset::Gb18030 => {
+                self.decoder().encode(text).0.into_owned()
+            }
+        }
+    }
+
+    /// The text a client decoding the body with the right encoding should end up with
+    fn expected_text(&self) -> String {
+        self.decoder()
+            .decode_with_bom_removal(&self.encode(TEXT))
+            .0
+            .into_owned()
+    }
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct EncodingInfo {
+    name: &'static str,
+
+    /// Encoding actually used for the body
+    encoding: &'static str,
+
+    /// If the body starts with a byte order mark
+    bom: bool,
+    content_type: &'static str,
+    label: Label,
+
+    /// The body decoded using `encoding`
+    expected_text: String,
+}
+
+impl From<&Variant> for EncodingInfo {
+    fn from(value: &Variant) -> Self {
+        Self {
+            name: value.name,
+            encoding: value.charset.name(),
+            bom: matches!(value.charset, Charset::Utf16 { bom: true, .. }),
+            content_type: value.content_type,
+            label: value.label,
+            expected_text: value.charset.expected_text(),
+        }
+    }
+}
+
+/// Lists the available encodings along with the text each should decode to
+#[utoipa::path(
+    get,
+    path = "/encoding",
+    tag = "encoding",
+    responses((status = 200, description = "Variants available at `/encoding/{charset}`", body = Vec<EncodingInfo>))
+)]
+#[instrument]
+pub async fn encoding_list() -> Json<Vec<EncodingInfo>> {
+    Json(VARIANTS.iter().map(EncodingInfo::from).collect())
+}
+
+/// Returns the multilingual text encoded as described by `/encoding`
+#[utoipa::path(
+    get,
+    path = "/encoding/{charset}",
+    tag = "encoding",
+    params(("charset" = String, Path, description = "Name of the variant as listed by `/encoding`")),
+    responses(
+        (status = 200, description = "The encoded text", body = Vec<u8>, content_type = "text/plain"),
+        (status = 404, description = "No variant with that name", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn encoding_show(path: Path<String>) -> crate::Result<HttpResponse> {
+    let name = path.into_inner();
+    let variant = VARIANTS.iter().find(|x| x.name == name).ok_or_else(|| {
+        HandlerError::new(ErrorKind::NotFound, anyhow!("no encoding named: {name}"))
+    })?;
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, variant.content_type))
+        .body(variant.charset.encode(TEXT)))
+}
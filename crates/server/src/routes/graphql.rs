@@ -0,0 +1,504 @@
+//! GraphQL endpoint with a small built-in schema of users and posts for testing clients offline
+//!
+//! Requests are sent as described by [GraphQL over HTTP](https://graphql.github.io/graphql-over-http/draft/),
+//! with `GET` and the `query`, `variables` and `operationName` query parameters or with
+//! `POST` and a JSON body (an array of requests is executed as a batch). A `POST` with
+//! `Content-Type: application/graphql` is also accepted with the query as the body.
+//!
+//! The data is fixed so every request sees the same users and posts, `createPost`
+//! returns the post it would have created without storing it.
+//!
+//! `User.posts` and `Post.author` refer to each other, so queries are limited to
+//! 16 levels of nesting and 500 fields and batches to 16 requests to keep nested queries
+//! from growing without bound.
+use std::{borrow::Cow, sync::LazyLock};
+
+use actix_web::{
+    http::{
+        header::{self, HeaderValue},
+        Method,
+    },
+    web::{Bytes, Json, Query},
+    HttpMessage as _, HttpRequest, HttpResponse,
+};
+use anyhow::{anyhow, Context as _};
+use async_graphql::{
+    parser::{
+        parse_query,
+        types::{OperationType, Selection, SelectionSet},
+    },
+    BatchRequest, EmptySubscription, Error, Object, Request, Schema, Variables, ID,
+};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{ErrorKind, HandlerError, ProblemDetails};
+
+type MockSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+/// Deepest nesting of selections allowed, enough for the usual introspection query
+const MAX_DEPTH: usize = 16;
+
+/// Most fields a query may select in total
+const MAX_COMPLEXITY: usize = 500;
+
+/// Most requests a batch may hold
+const MAX_BATCH: usize = 16;
+
+static SCHEMA: LazyLock<MockSchema> = LazyLock::new(|| {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+});
+
+#[derive(Clone, Copy)]
+struct User {
+    id: u64,
+    name: &'static str,
+    email: &'static str,
+}
+
+#[derive(Clone)]
+struct Post {
+    id: u64,
+    author_id: u64,
+    title: Cow<'static, str>,
+    body: Cow<'static, str>,
+}
+
+const USERS: &[User] = &[
+    User {
+        id: 1,
+        name: "Ada Lovelace",
+        email: "ada@example.com",
+    },
+    User {
+        id: 2,
+        name: "Alan Turing",
+        email: "alan@example.com",
+    },
+    User {
+        id: 3,
+        name: "Grace Hopper",
+        email: "grace@example.com",
+    },
+];
+
+const POSTS: &[Post] = &[
+    Post {
+        id: 1,
+        author_id: 1,
+        title: Cow::Borrowed("Notes on the Analytical Engine"),
+        body: Cow::Borrowed("The engine might compose elaborate pieces of music."),
+    },
+    Post {
+        id: 2,
+        author_id: 2,
+        title: Cow::Borrowed("Computing Machinery and Intelligence"),
+        body: Cow::Borrowed("I propose to consider the question, can machines think?"),
+    },
+    Post {
+        id: 3,
+        author_id: 2,
+        title: Cow::Borrowed("On Computable Numbers"),
+        body: Cow::Borrowed("The computable numbers may be described briefly as the real numbers whose expressions as a decimal are calculable by finite means."),
+    },
+    Post {
+        id: 4,
+        author_id: 3,
+        title: Cow::Borrowed("The First Bug"),
+        body: Cow::Borrowed("Relay #70 Panel F (moth) in relay."),
+    },
+];
+
+fn parse_id(id: &ID) -> async_graphql::Result<u64> {
+    id.parse()
+        .map_err(|_| Error::new(format!("IDs are numbers but found: {:?}", id.as_str())))
+}
+
+fn find_user(id: u64) -> Option<User> {
+    USERS.iter().find(|x| x.id == id).copied()
+}
+
+fn posts_by(author_id: Option<u64>) -> Vec<Post> {
+    POSTS
+        .iter()
+        .filter(|x| author_id.is_none_or(|id| x.author_id == id))
+        .cloned()
+        .collect()
+}
+
+#[Object]
+impl User {
+    async fn id(&self) -> ID {
+        self.id.into()
+    }
+
+    async fn name(&self) -> &str {
+        self.name
+    }
+
+    async fn email(&self) -> &str {
+        self.email
+    }
+
+    async fn posts(&self) -> Vec<Post> {
+        posts_by(Some(self.id))
+    }
+
+    /// Always fails so partial results can be tested, the other fields are still returned
+    async fn secret(&self) -> async_graphql::Result<Option<String>> {
+        Err(Error::new(format!(
+            "not allowed to see the secret of user {}",
+            self.id
+        )))
+    }
+}
+
+#[Object]
+impl Post {
+    async fn id(&self) -> ID {
+        self.id.into()
+    }
+
+    async fn title(&self) -> &str {
+        &self.title
+    }
+
+    async fn body(&self) -> &str {
+        &self.body
+    }
+
+    async fn author(&self) -> async_graphql::Result<User> {
+        find_user(self.author_id)
+            .ok_or_else(|| Error::new(format!("no user with ID {}", self.author_id)))
+    }
+}
+
+struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn users(&self) -> Vec<User> {
+        USERS.to_vec()
+    }
+
+    /// `null` with an error if there is no user with the ID
+    async fn user(&self, id: ID) -> async_graphql::Result<Option<User>> {
+        let id = parse_id(&id)?;
+        find_user(id)
+            .map(Some)
+            .ok_or_else(|| Error::new(format!("no user with ID {id}")))
+    }
+
+    /// Every post or only those written by `authorId`
+    async fn posts(&self, author_id: Option<ID>) -> async_graphql::Result<Vec<Post>> {
+        let author_id = author_id.as_ref().map(parse_id).transpose()?;
+        Ok(posts_by(author_id))
+    }
+
+    /// `null` with an error if there is no post with the ID
+    async fn post(&self, id: ID) -> async_graphql::Result<Option<Post>> {
+        let id = parse_id(&id)?;
+        POSTS
+            .iter()
+            .find(|x| x.id == id)
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| Error::new(format!("no post with ID {id}")))
+    }
+
+    /// Always fails with `message`
+    async fn fail(&self, message: Option<String>) -> async_graphql::Result<Option<String>> {
+        Err(Error::new(
+            message.unwrap_or_else(|| "failed on purpose".to_string()),
+        ))
+    }
+}
+
+struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Returns the post that would be created, nothing is stored
+    async fn create_post(
+        &self,
+        author_id: ID,
+        title: String,
+        body: String,
+    ) -> async_graphql::Result<Post> {
+        let author_id = parse_id(&author_id)?;
+        if find_user(author_id).is_none() {
+            return Err(Error::new(format!("no user with ID {author_id}")));
+        }
+        Ok(Post {
+            id: POSTS.len() as u64 + 1,
+            author_id,
+            title: title.into(),
+            body: body.into(),
+        })
+    }
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct GraphqlParams {
+    /// The GraphQL document, only used for `GET`
+    query: String,
+
+    /// JSON object with the values of the variables
+    variables: Option<String>,
+
+    /// Operation to run if the document has more than one
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+}
+
+fn bad_request(error: anyhow::Error) -> HandlerError {
+    HandlerError::new(ErrorKind::BadRequest, error)
+}
+
+/// Reads the request from the query string for `GET` and from the body otherwise
+fn parse_request(req: &HttpRequest, body: &Bytes) -> crate::Result<BatchRequest> {
+    if req.method() == Method::GET {
+        let Query(params) = Query::<GraphqlParams>::from_query(req.query_string())
+            .context("invalid GraphQL query parameters")
+            .map_err(bad_request)?;
+        let mut request = Request::new(params.query);
+        if let Some(variables) = params.variables {
+            let variables = serde_json::from_str(&variables)
+                .context("variables must be a JSON object")
+                .map_err(bad_request)?;
+            request = request.variables(Variables::from_json(variables));
+        }
+        if let Some(operation_name) = params.operation_name {
+            request = request.operation_name(operation_name);
+        }
+        return Ok(BatchRequest::Single(request));
+    }
+    match req.content_type() {
+        "" | "application/json" => {
+            let batch: BatchRequest = serde_json::from_slice(body)
+                .context("body is not a GraphQL request")
+                .map_err(bad_request)?;
+            if let BatchRequest::Batch(requests) = &batch {
+                if requests.len() > MAX_BATCH {
+                    return Err(bad_request(anyhow!(
+                        "batches are limited to {MAX_BATCH} requests but found {}",
+                        requests.len()
+                    )));
+                }
+            }
+            Ok(batch)
+        }
+        "application/graphql" => {
+            let query = std::str::from_utf8(body)
+                .context("body is not UTF-8")
+                .map_err(bad_request)?;
+            Ok(BatchRequest::Single(Request::new(query)))
+        }
+        other => Err(HandlerError::new(
+            ErrorKind::UnsupportedMediaType,
+            anyhow!("expected application/json or application/graphql but found: {other}"),
+        )),
+    }
+}
+
+/// The type of operation that will run, `None` if it cannot be determined before executing
+fn operation_type(request: &Request) -> Option<OperationType> {
+    let document = parse_query(&request.query).ok()?;
+    document
+        .operations
+        .iter()
+        .find(|(name, _)| {
+            request.operation_name.is_none()
+                || name.map(|x| x.as_str()) == request.operation_name.as_deref()
+        })
+        .map(|(_, operation)| operation.node.ty)
+}
+
+/// Runs queries and mutations against the built-in users and posts schema
+#[utoipa::path(
+    method(get, post),
+    path = "/graphql",
+    tag = "graphql",
+    params(GraphqlParams),
+    request_body(content = Object, description = "GraphQL request (or an array of them) for `POST`", content_type = "application/json"),
+    responses(
+        (status = 200, description = "GraphQL response with `data` and any `errors`", body = Object),
+        (status = 400, description = "The request could not be read or the batch is too large", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 405, description = "Mutations are only allowed with `POST`, `Allow` is set to `POST`", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 415, description = "Unsupported `Content-Type`", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip(body))]
+pub async fn graphql(req: HttpRequest, body: Bytes) -> crate::Result<HttpResponse> {
+    let batch = parse_request(&req, &body)?;
+    if let BatchRequest::Single(request) = &batch {
+        if req.method() == Method::GET && operation_type(request) == Some(OperationType::Mutation) {
+            let mut res = HttpResponse::from_error(HandlerError::new(
+                ErrorKind::MethodNotAllowed,
+                anyhow!("mutations must be sent with POST"),
+            ));
+            res.headers_mut()
+                .insert(header::ALLOW, HeaderValue::from_static("POST"));
+            return Ok(res);
+        }
+    }
+    Ok(HttpResponse::Ok().json(SCHEMA.execute_batch(batch).await))
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct GraphqlEcho {
+    query: String,
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+    #[schema(value_type = Object)]
+    variables: serde_json::Value,
+    operations: Vec<EchoOperation>,
+    fragments: Vec<EchoFragment>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct EchoOperation {
+    /// Not set for an anonymous operation
+    name: Option<String>,
+
+    /// `query`, `mutation` or `subscription`
+    operation_type: String,
+
+    /// Variable definitions as written, for example `$id: ID! = 1`
+    variables: Vec<String>,
+    selections: Vec<EchoSelection>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct EchoFragment {
+    name: String,
+    type_condition: String,
+    selections: Vec<EchoSelection>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EchoSelection {
+    Field {
+        name: String,
+        alias: Option<String>,
+
+        /// Values as written in GraphQL syntax
+        arguments: Vec<(String, String)>,
+        #[schema(no_recursion)]
+        selections: Vec<EchoSelection>,
+    },
+    FragmentSpread {
+        name: String,
+    },
+    InlineFragment {
+        type_condition: Option<String>,
+        #[schema(no_recursion)]
+        selections: Vec<EchoSelection>,
+    },
+}
+
+fn echo_selections(selection_set: &SelectionSet) -> Vec<EchoSelection> {
+    selection_set
+        .items
+        .iter()
+        .map(|item| match &item.node {
+            Selection::Field(field) => EchoSelection::Field {
+                name: field.node.name.to_string(),
+                alias: field.node.alias.as_ref().map(|x| x.to_string()),
+                arguments: field
+                    .node
+                    .arguments
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                selections: echo_selections(&field.node.selection_set.node),
+            },
+            Selection::FragmentSpread(spread) => EchoSelection::FragmentSpread {
+                name: spread.node.fragment_name.to_string(),
+            },
+            Selection::InlineFragment(fragment) => EchoSelection::InlineFragment {
+                type_condition: fragment
+                    .node
+                    .type_condition
+                    .as_ref()
+                    .map(|x| x.node.on.to_string()),
+                selections: echo_selections(&fragment.node.selection_set.node),
+            },
+        })
+        .collect()
+}
+
+impl GraphqlEcho {
+    fn new(request: Request) -> crate::Result<Self> {
+        let document = parse_query(&request.query)
+            .context("invalid GraphQL document")
+            .map_err(bad_request)?;
+        let mut operations: Vec<_> = document
+            .operations
+            .iter()
+            .map(|(name, operation)| EchoOperation {
+                name: name.map(|x| x.to_string()),
+                operation_type: operation.node.ty.to_string(),
+                variables: operation
+                    .node
+                    .variable_definitions
+                    .iter()
+                    .map(|x| {
+                        let x = &x.node;
+                        match &x.default_value {
+                            Some(default) => format!("${}: {} = {}", x.name, x.var_type, default),
+                            None => format!("${}: {}", x.name, x.var_type),
+                        }
+                    })
+                    .collect(),
+                selections: echo_selections(&operation.node.selection_set.node),
+            })
+            .collect();
+        operations.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut fragments: Vec<_> = document
+            .fragments
+            .iter()
+            .map(|(name, fragment)| EchoFragment {
+                name: name.to_string(),
+                type_condition: fragment.node.type_condition.node.on.to_string(),
+                selections: echo_selections(&fragment.node.selection_set.node),
+            })
+            .collect();
+        fragments.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Self {
+            variables: serde_json::to_value(&request.variables)
+                .context("failed to convert variables to JSON")?,
+            query: request.query,
+            operation_name: request.operation_name,
+            operations,
+            fragments,
+        })
+    }
+}
+
+/// Returns the parsed operations, variables and operationName without executing anything
+#[utoipa::path(
+    method(get, post),
+    path = "/graphql/echo",
+    tag = "graphql",
+    params(GraphqlParams),
+    request_body(content = Object, description = "GraphQL request for `POST`", content_type = "application/json"),
+    responses(
+        (status = 200, description = "What was parsed from the request", body = GraphqlEcho),
+        (status = 400, description = "The request or document could not be parsed", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 415, description = "Unsupported `Content-Type`", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip(body))]
+pub async fn graphql_echo(req: HttpRequest, body: Bytes) -> crate::Result<Json<GraphqlEcho>> {
+    match parse_request(&req, &body)? {
+        BatchRequest::Single(request) => Ok(Json(GraphqlEcho::new(request)?)),
+        BatchRequest::Batch(_) => Err(bad_request(anyhow!("batches cannot be echoed"))),
+    }
+}
@@ -0,0 +1,633 @@
+//! Echo service for testing gRPC-Web clients, described by `proto/echo.proto`
+//!
+//! Both `application/grpc-web` and the base64 encoded `application/grpc-web-text` are
+//! served on the same listeners as every other route. Native gRPC (`application/grpc`)
+//! needs HTTP/2 trailers which actix-web cannot send, so it is served by [`serve_native`]
+//! on the addresses in the `grpc` config section instead. Native requests sent to the
+//! other listeners get a trailers-only response with the status asked for or
+//! `UNIMPLEMENTED`.
+//!
+//! The status and trailers of the response are set with the [`STATUS_HEADER`],
+//! [`MESSAGE_HEADER`] and [`TRAILER_HEADER_PREFIX`] request headers. Streamed
+//! responses are still sent before a status that is not `OK`, unary ones are not.
+//!
+//! Client streaming and bidirectional calls are half duplex, every request message is
+//! read before the first response is sent.
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    http::{header::CONTENT_TYPE, StatusCode},
+    rt::time::sleep,
+    web::{Bytes, Path},
+    HttpMessage as _, HttpRequest, HttpResponse,
+};
+use anyhow::{anyhow, bail, Context as _};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use futures_util::{stream, StreamExt as _};
+use h2::{server::SendResponse, RecvStream, SendStream};
+use prost::Message;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, info_span, instrument, warn, Instrument as _, Span};
+
+use crate::{
+    metrics::{metrics, ConnectionGuard, InFlightGuard},
+    reload::LiveConfig,
+    ErrorKind, HandlerError, ProblemDetails, RequestId, REQUEST_ID_HEADER,
+};
+
+/// Request header with the numeric status code to respond with, `OK` if not set
+pub const STATUS_HEADER: &str = "x-test-grpc-status";
+
+/// Request header with the `grpc-message` to respond with
+pub const MESSAGE_HEADER: &str = "x-test-grpc-message";
+
+/// Request headers starting with this are sent back as trailers without the prefix
+pub const TRAILER_HEADER_PREFIX: &str = "x-test-grpc-trailer-";
+
+const METHODS: &[&str] = &["Unary", "ServerStream", "ClientStream", "Bidi"];
+
+/// Route label of native calls in the metrics, the same as for gRPC-Web calls
+const NATIVE_ROUTE: &str = "/http_test.echo.Echo/{method}";
+
+/// Number of responses sent by `ServerStream` if not specified
+const DEFAULT_COUNT: u32 = 3;
+
+/// Largest number of responses `ServerStream` will send
+const MAX_COUNT: u32 = 1000;
+
+/// Longest delay before a single response
+const MAX_DELAY_MS: u32 = 10_000;
+
+/// Largest native request body read, the default message size limit of gRPC libraries
+const MAX_NATIVE_BODY: usize = 4 * 1024 * 1024;
+
+const CODE_OK: u32 = 0;
+const CODE_INVALID_ARGUMENT: u32 = 3;
+const CODE_RESOURCE_EXHAUSTED: u32 = 8;
+const CODE_UNIMPLEMENTED: u32 = 12;
+const CODE_INTERNAL: u32 = 13;
+
+/// Set on frames holding trailers instead of a message
+const TRAILERS_FLAG: u8 = 0x80;
+const COMPRESSED_FLAG: u8 = 0x01;
+
+#[derive(Clone, PartialEq, Message)]
+struct EchoRequest {
+    #[prost(string, tag = "1")]
+    message: String,
+    #[prost(uint32, tag = "2")]
+    count: u32,
+    #[prost(uint32, tag = "3")]
+    delay_ms: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct EchoResponse {
+    #[prost(string, tag = "1")]
+    message: String,
+    #[prost(uint32, tag = "2")]
+    index: u32,
+}
+
+impl EchoRequest {
+    fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms.min(MAX_DELAY_MS).into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Protocol {
+    Web,
+    WebText,
+    Native,
+}
+
+impl Protocol {
+    fn of(req: &HttpRequest) -> Option<Self> {
+        match req.content_type() {
+            "application/grpc-web" | "application/grpc-web+proto" => Some(Self::Web),
+            "application/grpc-web-text" | "application/grpc-web-text+proto" => Some(Self::WebText),
+            "application/grpc" | "application/grpc+proto" => Some(Self::Native),
+            _ => None,
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Protocol::Web => "application/grpc-web+proto",
+            Protocol::WebText => "application/grpc-web-text+proto",
+            Protocol::Native => "application/grpc+proto",
+        }
+    }
+}
+
+/// The status and trailers sent at the end of a response
+#[derive(Debug)]
+struct Status {
+    code: u32,
+    message: Option<String>,
+    trailers: Vec<(String, String)>,
+}
+
+impl Status {
+    fn new(code: u32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: Some(message.into()),
+            trailers: vec![],
+        }
+    }
+
+    /// The status asked for with the request headers given as `(name, value)` pairs
+    fn requested<'a>(
+        headers: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    ) -> anyhow::Result<Self> {
+        let mut result = Self {
+            code: CODE_OK,
+            message: None,
+            trailers: vec![],
+        };
+        for (name, value) in headers {
+            let ascii = || {
+                std::str::from_utf8(value)
+                    .ok()
+                    .filter(|x| x.is_ascii())
+                    .with_context(|| format!("{name} must be ASCII"))
+            };
+            if name == STATUS_HEADER {
+                result.code = ascii()?
+                    .parse()
+                    .ok()
+                    .filter(|x| *x <= 16)
+                    .with_context(|| {
+                        format!("{STATUS_HEADER} must be a status code from 0 to 16")
+                    })?;
+            } else if name == MESSAGE_HEADER {
+                result.message = Some(ascii()?.to_string());
+            } else if let Some(trailer) = name.strip_prefix(TRAILER_HEADER_PREFIX) {
+                result
+                    .trailers
+                    .push((trailer.to_string(), ascii()?.to_string()));
+            }
+        }
+        Ok(result)
+    }
+
+    fn is_ok(&self) -> bool {
+        self.code == CODE_OK
+    }
+
+    /// `grpc-status`, `grpc-message` and the extra trailers in the order they are sent
+    fn fields(&self) -> Vec<(String, String)> {
+        let mut result = vec![("grpc-status".to_string(), self.code.to_string())];
+        if let Some(message) = &self.message {
+            result.push(("grpc-message".to_string(), percent_encode(message)));
+        }
+        result.extend(self.trailers.iter().cloned());
+        result
+    }
+
+    /// Frame with the trailers sent at the end of a gRPC-Web response
+    fn to_frame(&self) -> Bytes {
+        let block: String = self
+            .fields()
+            .into_iter()
+            .map(|(name, value)| format!("{name}: {value}\r\n"))
+            .collect();
+        frame(TRAILERS_FLAG, block.as_bytes())
+    }
+
+    /// `fields` as an HTTP/2 header block
+    fn to_header_map(&self) -> anyhow::Result<http::HeaderMap> {
+        let mut result = http::HeaderMap::new();
+        for (name, value) in self.fields() {
+            let name = http::HeaderName::try_from(&name)
+                .with_context(|| format!("invalid trailer name: {name:?}"))?;
+            let value = http::HeaderValue::try_from(&value)
+                .with_context(|| format!("invalid value for {name}: {value:?}"))?;
+            result.append(name, value);
+        }
+        Ok(result)
+    }
+
+    /// Response with the status in the headers and no body
+    fn trailers_only(&self, protocol: Protocol) -> HttpResponse {
+        let mut builder = HttpResponse::Ok();
+        builder.insert_header((CONTENT_TYPE, protocol.content_type()));
+        for field in self.fields() {
+            builder.append_header(field);
+        }
+        builder.finish()
+    }
+}
+
+/// Percent encodes `grpc-message` as required by the gRPC spec
+fn percent_encode(message: &str) -> String {
+    message
+        .bytes()
+        .map(|x| match x {
+            b' '..=b'~' if x != b'%' => (x as char).to_string(),
+            _ => format!("%{x:02X}"),
+        })
+        .collect()
+}
+
+fn frame(flags: u8, payload: &[u8]) -> Bytes {
+    let mut result = Vec::with_capacity(payload.len() + 5);
+    result.push(flags);
+    result.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    result.extend_from_slice(payload);
+    result.into()
+}
+
+fn decode_requests(mut body: &[u8]) -> anyhow::Result<Vec<EchoRequest>> {
+    let mut result = vec![];
+    while !body.is_empty() {
+        let header = body.get(..5).context("incomplete message header")?;
+        if header[0] & COMPRESSED_FLAG != 0 {
+            bail!("compressed messages are not supported");
+        }
+        let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        let message = body.get(5..5 + len).context("incomplete message")?;
+        result.push(EchoRequest::decode(message).context("invalid EchoRequest")?);
+        body = &body[5 + len..];
+    }
+    Ok(result)
+}
+
+fn single(mut requests: Vec<EchoRequest>) -> Result<EchoRequest, Status> {
+    if requests.len() != 1 {
+        return Err(Status::new(
+            CODE_INTERNAL,
+            format!("expected one request message but found {}", requests.len()),
+        ));
+    }
+    Ok(requests.remove(0))
+}
+
+/// Responses of the method and the delay before each is sent
+fn respond(
+    method: &str,
+    requests: Vec<EchoRequest>,
+) -> Result<Vec<(Duration, EchoResponse)>, Status> {
+    let result = match method {
+        "Unary" => {
+            let request = single(requests)?;
+            vec![(
+                request.delay(),
+                EchoResponse {
+                    message: request.message,
+                    index: 0,
+                },
+            )]
+        }
+        "ServerStream" => {
+            let request = single(requests)?;
+            let count = match request.count {
+                0 => DEFAULT_COUNT,
+                count if count > MAX_COUNT => {
+                    return Err(Status::new(
+                        CODE_INVALID_ARGUMENT,
+                        format!("count may be at most {MAX_COUNT}"),
+                    ))
+                }
+                count => count,
+            };
+            (0..count)
+                .map(|index| {
+                    let response = EchoResponse {
+                        message: request.message.clone(),
+                        index,
+                    };
+                    (request.delay(), response)
+                })
+                .collect()
+        }
+        "ClientStream" => {
+            let delay = requests.iter().map(EchoRequest::delay).max();
+            let response = EchoResponse {
+                message: requests
+                    .iter()
+                    .map(|x| x.message.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                index: requests.len() as u32,
+            };
+            vec![(delay.unwrap_or_default(), response)]
+        }
+        "Bidi" => requests
+            .into_iter()
+            .zip(0..)
+            .map(|(request, index)| {
+                let delay = request.delay();
+                let response = EchoResponse {
+                    message: request.message,
+                    index,
+                };
+                (delay, response)
+            })
+            .collect(),
+        _ => {
+            let message = format!("no method named {method:?}, expected one of {METHODS:?}");
+            return Err(Status::new(CODE_UNIMPLEMENTED, message));
+        }
+    };
+    Ok(result)
+}
+
+/// Frames of the response messages and the delay before each, followed by the status
+///
+/// An error is sent as a trailers-only response.
+fn call(
+    method: &str,
+    body: &[u8],
+    status: Status,
+) -> Result<(Vec<(Duration, Bytes)>, Status), Status> {
+    let requests =
+        decode_requests(body).map_err(|err| Status::new(CODE_INTERNAL, format!("{err:#}")))?;
+    let streams_responses = matches!(method, "ServerStream" | "Bidi");
+    let mut responses = respond(method, requests)?;
+    if !status.is_ok() && !streams_responses {
+        responses.clear();
+    }
+    let frames = responses
+        .into_iter()
+        .map(|(delay, response)| (delay, frame(0, &response.encode_to_vec())))
+        .collect();
+    Ok((frames, status))
+}
+
+/// Calls a method of the `http_test.echo.Echo` service with gRPC-Web
+#[utoipa::path(
+    post,
+    path = "/http_test.echo.Echo/{method}",
+    tag = "grpc",
+    params(("method" = String, Path, description = "Unary, ServerStream, ClientStream or Bidi")),
+    request_body(content = Vec<u8>, description = "Length prefixed `EchoRequest` messages", content_type = "application/grpc-web+proto"),
+    responses(
+        (status = 200, description = "Length prefixed `EchoResponse` messages followed by the trailers", body = Vec<u8>, content_type = "application/grpc-web+proto"),
+        (status = 415, description = "Not a gRPC content type", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip(body))]
+pub async fn grpc_echo(
+    req: HttpRequest,
+    path: Path<String>,
+    body: Bytes,
+) -> crate::Result<HttpResponse> {
+    let protocol = Protocol::of(&req).ok_or_else(|| {
+        HandlerError::new(
+            ErrorKind::UnsupportedMediaType,
+            anyhow!(
+                "expected application/grpc-web or application/grpc-web-text but found: {}",
+                req.content_type()
+            ),
+        )
+    })?;
+    let headers = req.headers().iter();
+    let status =
+        match Status::requested(headers.map(|(name, value)| (name.as_str(), value.as_bytes()))) {
+            Ok(status) => status,
+            Err(err) => {
+                return Ok(
+                    Status::new(CODE_INVALID_ARGUMENT, format!("{err:#}")).trailers_only(protocol)
+                )
+            }
+        };
+    if protocol == Protocol::Native {
+        let status = if status.is_ok() {
+            Status::new(
+                CODE_UNIMPLEMENTED,
+                "native gRPC is only served on the addresses in grpc.listen, use gRPC-Web here",
+            )
+        } else {
+            status
+        };
+        return Ok(status.trailers_only(protocol));
+    }
+    let body = match protocol {
+        Protocol::WebText => match STANDARD.decode(body.trim_ascii()) {
+            Ok(body) => body.into(),
+            Err(err) => {
+                return Ok(
+                    Status::new(CODE_INTERNAL, format!("body is not base64: {err}"))
+                        .trailers_only(protocol),
+                )
+            }
+        },
+        _ => body,
+    };
+    let (frames, status) = match call(&path, &body, status) {
+        Ok(result) => result,
+        Err(status) => return Ok(status.trailers_only(protocol)),
+    };
+    let frames = frames
+        .into_iter()
+        .chain([(Duration::ZERO, status.to_frame())]);
+    let body = stream::iter(frames).then(move |(delay, frame)| async move {
+        sleep(delay).await;
+        let frame = match protocol {
+            // Each frame is encoded separately so it can be decoded as soon as it arrives
+            Protocol::WebText => Bytes::from(STANDARD.encode(&frame)),
+            _ => frame,
+        };
+        Ok::<_, Infallible>(frame)
+    });
+    Ok(HttpResponse::Ok()
+        .content_type(protocol.content_type())
+        .streaming(body))
+}
+
+/// Serves native gRPC over HTTP/2 without TLS (h2c) on `listener` until the task is dropped
+///
+/// Calls are only answered while the `grpc` route group is on in `live` and are counted in
+/// the metrics and logged with a request ID like requests on the other listeners.
+pub async fn serve_native(
+    listener: std::net::TcpListener,
+    live: Arc<LiveConfig>,
+) -> anyhow::Result<()> {
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    loop {
+        let (socket, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                // Usually out of file descriptors, retrying right away would spin
+                warn!("failed to accept native gRPC connection: {err}");
+                sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let live = Arc::clone(&live);
+        tokio::spawn(async move {
+            let _connection = ConnectionGuard::new();
+            if let Err(err) = serve_connection(socket, live).await {
+                debug!(%peer, "native gRPC connection ended: {err:#}");
+            }
+        });
+    }
+}
+
+async fn serve_connection(socket: TcpStream, live: Arc<LiveConfig>) -> anyhow::Result<()> {
+    let mut connection = h2::server::handshake(socket).await?;
+    while let Some(result) = connection.accept().await {
+        let (request, respond) = result?;
+        let native = NativeCall::new(&request);
+        let groups_allow = live.current().routes.grpc;
+        tokio::spawn(async move {
+            if let Err(err) = native_call(request, respond, native, groups_allow).await {
+                debug!("native gRPC call failed: {err:#}");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// A native call in progress, recorded in the metrics when its response headers are sent
+struct NativeCall {
+    span: Span,
+    method: String,
+    request_id: RequestId,
+    start: Instant,
+    _in_flight: InFlightGuard,
+}
+
+impl NativeCall {
+    fn new(request: &http::Request<RecvStream>) -> Self {
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER.as_str())
+            .and_then(|x| RequestId::from_client(x.as_bytes()))
+            .unwrap_or_else(RequestId::generate);
+        let span = info_span!(
+            "native gRPC call",
+            http.method = %request.method(),
+            http.target = %request.uri().path(),
+            x_request_id = %request_id,
+        );
+        Self {
+            span,
+            method: request.method().to_string(),
+            request_id,
+            start: Instant::now(),
+            _in_flight: InFlightGuard::new(),
+        }
+    }
+
+    /// Sends the response headers with the request ID added
+    fn respond(
+        &self,
+        respond: &mut SendResponse<Bytes>,
+        mut response: http::Response<()>,
+        end_of_stream: bool,
+    ) -> anyhow::Result<SendStream<Bytes>> {
+        response.headers_mut().insert(
+            http::HeaderName::try_from(REQUEST_ID_HEADER.as_str())?,
+            http::HeaderValue::from_str(self.request_id.as_str())?,
+        );
+        let status = StatusCode::from_u16(response.status().as_u16())?;
+        metrics().record_request(&self.method, NATIVE_ROUTE, status, self.start.elapsed());
+        info!(
+            http.status_code = status.as_u16(),
+            grpc_status = response
+                .headers()
+                .get("grpc-status")
+                .and_then(|x| x.to_str().ok()),
+            "native gRPC response"
+        );
+        Ok(respond.send_response(response, end_of_stream)?)
+    }
+
+    /// Responds with the status in the headers and no body
+    fn trailers_only(
+        &self,
+        mut respond: SendResponse<Bytes>,
+        status: &Status,
+    ) -> anyhow::Result<()> {
+        let mut response = http::Response::builder()
+            .header(http::header::CONTENT_TYPE, Protocol::Native.content_type())
+            .body(())?;
+        response.headers_mut().extend(status.to_header_map()?);
+        self.respond(&mut respond, response, true)?;
+        Ok(())
+    }
+}
+
+/// Responds to one native gRPC call with the same behaviour as [`grpc_echo`]
+async fn native_call(
+    request: http::Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    native: NativeCall,
+    groups_allow: bool,
+) -> anyhow::Result<()> {
+    let span = native.span.clone();
+    async move {
+        if !groups_allow {
+            let status = Status::new(CODE_UNIMPLEMENTED, "the grpc route group is turned off");
+            return native.trailers_only(respond, &status);
+        }
+        let (parts, mut body) = request.into_parts();
+        let content_type = parts
+            .headers
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or_default();
+        if !matches!(content_type, "application/grpc" | "application/grpc+proto") {
+            let response = http::Response::builder()
+                .status(http::StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                .body(())?;
+            native.respond(&mut respond, response, true)?;
+            return Ok(());
+        }
+        let headers = parts.headers.iter();
+        let status =
+            Status::requested(headers.map(|(name, value)| (name.as_str(), value.as_bytes())));
+        let method = parts.uri.path().strip_prefix("/http_test.echo.Echo/");
+
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk?;
+            body.flow_control().release_capacity(chunk.len())?;
+            data.extend_from_slice(&chunk);
+            if data.len() > MAX_NATIVE_BODY {
+                let status = Status::new(
+                    CODE_RESOURCE_EXHAUSTED,
+                    format!("request body is larger than {MAX_NATIVE_BODY} bytes"),
+                );
+                return native.trailers_only(respond, &status);
+            }
+        }
+
+        let result = match (status, method) {
+            (Err(err), _) => Err(Status::new(CODE_INVALID_ARGUMENT, format!("{err:#}"))),
+            (Ok(_), None) => Err(Status::new(
+                CODE_UNIMPLEMENTED,
+                format!(
+                    "no service at {}, expected http_test.echo.Echo",
+                    parts.uri.path()
+                ),
+            )),
+            (Ok(status), Some(method)) => call(method, &data, status),
+        };
+        let (frames, status) = match result {
+            Ok(result) => result,
+            Err(status) => return native.trailers_only(respond, &status),
+        };
+        let response = http::Response::builder()
+            .header(http::header::CONTENT_TYPE, Protocol::Native.content_type())
+            .body(())?;
+        let mut stream = native.respond(&mut respond, response, false)?;
+        for (delay, frame) in frames {
+            sleep(delay).await;
+            stream.send_data(frame, false)?;
+        }
+        stream.send_trailers(status.to_header_map()?)?;
+        Ok(())
+    }
+    .instrument(span)
+    .await
+}
@@ -0,0 +1,60 @@
+//! Endpoints for orchestrators and CI to check on the server
+use std::time::Instant;
+
+use actix_web::{web::Data, web::Json, HttpResponse};
+use serde::Serialize;
+use tracing::instrument;
+use utoipa::ToSchema;
+
+/// Information about the running server, created once at startup
+#[derive(Debug)]
+pub struct ServerInfo {
+    started: Instant,
+}
+
+impl Default for ServerInfo {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct VersionInfo {
+    version: &'static str,
+    git_commit: &'static str,
+    build_time: &'static str,
+    features: Vec<&'static str>,
+    uptime_secs: u64,
+}
+
+/// Liveness check, responds as long as the server is able to handle requests
+#[utoipa::path(get, path = "/healthz", tag = "health", responses((status = 200, body = String)))]
+#[instrument]
+pub async fn health_check() -> HttpResponse {
+    HttpResponse::Ok().body("ok\n")
+}
+
+/// Readiness check, the server has no dependencies to wait on so it is ready once it is listening
+#[utoipa::path(get, path = "/readyz", tag = "health", responses((status = 200, body = String)))]
+#[instrument]
+pub async fn readiness_check() -> HttpResponse {
+    HttpResponse::Ok().body("ready\n")
+}
+
+/// Build information and uptime of the server
+#[utoipa::path(get, path = "/version", tag = "health", responses((status = 200, body = VersionInfo)))]
+#[instrument]
+pub async fn version_show(server_info: Data<ServerInfo>) -> Json<VersionInfo> {
+    Json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("GIT_COMMIT"),
+        build_time: env!("BUILD_TIME"),
+        features: env!("ENABLED_FEATURES")
+            .split(',')
+            .filter(|x| !x.is_empty())
+            .collect(),
+        uptime_secs: server_info.started.elapsed().as_secs(),
+    })
+}
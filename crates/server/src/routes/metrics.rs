@@ -0,0 +1,22 @@
+use actix_web::{web::Data, HttpResponse};
+use tracing::instrument;
+
+use super::RateLimitBuckets;
+
+/// Shows the server's metrics in the Prometheus text format
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "health",
+    responses((status = 200, description = "Prometheus text format", body = String, content_type = "text/plain; version=0.0.4"))
+)]
+#[instrument]
+pub async fn metrics_show(
+    rate_limit_buckets: Data<RateLimitBuckets>,
+) -> crate::Result<HttpResponse> {
+    let metrics = crate::metrics::metrics();
+    metrics.set_subsystem_items("rate_limit_buckets", rate_limit_buckets.count());
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(metrics.encode()?))
+}
@@ -0,0 +1,67 @@
+//! OpenAPI description of every route, served with an offline copy of Swagger UI
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+use super::{
+    admin, anything, bytes, cookie_stress, cookies, dashboard, encoding, graphql, grpc, health,
+    metrics, protocol, proxy, rate_limit, samples, scoped_cookies, stream, webhooks,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "HTTP Test Server",
+        description = "Server used to test HTTP requests and cookie handling",
+        license(name = "MIT OR Apache-2.0")
+    ),
+    paths(
+        crate::echo_handler,
+        crate::echo_raw_handler,
+        anything::anything,
+        anything::response,
+        anything::response_headers,
+        graphql::graphql,
+        graphql::graphql_echo,
+        grpc::grpc_echo,
+        cookies::cookie_show,
+        cookies::cookie_set,
+        cookies::cookie_expire,
+        cookie_stress::cookie_stress_list,
+        cookie_stress::cookie_stress,
+        scoped_cookies::scoped_cookies_show,
+        scoped_cookies::scoped_cookies_set,
+        scoped_cookies::scoped_cookies_expected,
+        health::health_check,
+        health::readiness_check,
+        health::version_show,
+        metrics::metrics_show,
+        bytes::range,
+        bytes::random_bytes,
+        bytes::stream_bytes,
+        stream::stream_json,
+        stream::stream_json_checksum,
+        stream::ndjson,
+        rate_limit::rate_limit_take,
+        rate_limit::rate_limit_status,
+        rate_limit::rate_limit_reset,
+        samples::samples_list,
+        samples::sample_show,
+        encoding::encoding_list,
+        encoding::encoding_show,
+        protocol::protocol,
+        admin::admin_reload,
+        proxy::captures_list,
+        proxy::captures_clear,
+        dashboard::dashboard_page,
+        dashboard::dashboard_events,
+        webhooks::webhook_send,
+        webhooks::webhook_deliveries,
+        webhooks::webhook_deliveries_clear,
+    )
+)]
+pub struct ApiDoc;
+
+/// Serves the spec at `/openapi.json` and the docs page at `/docs/`
+pub fn docs_service() -> SwaggerUi {
+    SwaggerUi::new("/docs/{_:.*}").url("/openapi.json", ApiDoc::openapi())
+}
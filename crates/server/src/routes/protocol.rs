@@ -0,0 +1,50 @@
+//! Reports how the request reached the server to check HTTP/2 and connection reuse
+//!
+//! HTTP/2 is negotiated with ALPN on the TLS listeners, the plain listeners also
+//! accept HTTP/2 from clients with prior knowledge (h2c) unless `server.h2c` is off.
+use actix_web::{web::Json, HttpMessage as _, HttpRequest};
+use serde::Serialize;
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::connection::{ConnectionInfo, RequestNumber};
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ProtocolInfo {
+    /// HTTP version of the request (for example `HTTP/1.1` or `HTTP/2.0`)
+    version: String,
+    tls: bool,
+
+    /// Protocol selected by ALPN, only set for TLS connections that negotiated one
+    alpn: Option<String>,
+
+    /// Requests with the same ID were sent on the same connection
+    connection_id: Option<u64>,
+
+    /// Position of the request on its connection starting from 1
+    request_number: Option<u64>,
+
+    /// If an earlier request was sent on the same connection
+    reused: bool,
+}
+
+/// Reports the negotiated HTTP version, ALPN value and whether the connection was reused
+#[utoipa::path(
+    get,
+    path = "/protocol",
+    tag = "protocol",
+    responses((status = 200, description = "Details of the request's connection", body = ProtocolInfo))
+)]
+#[instrument]
+pub async fn protocol(req: HttpRequest) -> Json<ProtocolInfo> {
+    let connection = req.conn_data::<ConnectionInfo>();
+    let request_number = req.extensions().get::<RequestNumber>().map(|x| x.0);
+    Json(ProtocolInfo {
+        version: format!("{:?}", req.version()),
+        tls: connection.is_some_and(|x| x.tls),
+        alpn: connection.and_then(|x| x.alpn.clone()),
+        connection_id: connection.map(|x| x.id),
+        request_number,
+        reused: request_number.is_some_and(|x| x > 1),
+    })
+}
@@ -0,0 +1,33 @@
+//! Inspection of the traffic recorded by the proxy, see [`crate::proxy`]
+use actix_web::{
+    web::{Data, Json},
+    HttpResponse,
+};
+use tracing::instrument;
+
+use crate::proxy::{Capture, Proxy};
+
+/// Lists the exchanges recorded by the proxy, oldest first
+#[utoipa::path(
+    get,
+    path = "/_proxy/captures",
+    tag = "proxy",
+    responses((status = 200, description = "Requests and responses as received by the proxy", body = Vec<Capture>))
+)]
+#[instrument]
+pub async fn captures_list(proxy: Data<Proxy>) -> Json<Vec<Capture>> {
+    Json(proxy.captures.list())
+}
+
+/// Removes every recorded exchange
+#[utoipa::path(
+    delete,
+    path = "/_proxy/captures",
+    tag = "proxy",
+    responses((status = 204, description = "Captures removed"))
+)]
+#[instrument]
+pub async fn captures_clear(proxy: Data<Proxy>) -> HttpResponse {
+    proxy.captures.clear();
+    HttpResponse::NoContent().finish()
+}
@@ -0,0 +1,297 @@
+//! Token buckets used to simulate rate limited endpoints
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    http::header::RETRY_AFTER,
+    web::{Data, Json, Path, Query},
+    HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{methods::is_head, ErrorKind, HandlerError, ProblemDetails};
+
+/// Most buckets kept, the one used longest ago is removed to make room for a new one
+const MAX_BUCKETS: usize = 1000;
+
+/// Buckets by name, shared by all workers
+#[derive(Debug, Default)]
+pub struct RateLimitBuckets(Mutex<HashMap<String, TokenBucket>>);
+
+impl RateLimitBuckets {
+    /// Number of buckets that currently exist
+    pub fn count(&self) -> usize {
+        self.0.lock().expect("rate limit mutex poisoned").len()
+    }
+
+    /// Current state of every bucket sorted by name
+    pub fn statuses(&self) -> Vec<BucketStatus> {
+        let mut guard = self.0.lock().expect("rate limit mutex poisoned");
+        let mut result: Vec<_> = guard
+            .iter_mut()
+            .map(|(name, bucket)| {
+                bucket.refill();
+                bucket.status(name)
+            })
+            .collect();
+        result.sort_by(|a, b| a.bucket.cmp(&b.bucket));
+        result
+    }
+}
+
+/// Configuration of a bucket as sent in the query string
+#[derive(Deserialize, IntoParams, Debug, Clone, Copy)]
+#[into_params(parameter_in = Query)]
+pub struct BucketQuery {
+    /// Maximum number of tokens the bucket can hold, defaults to 10
+    capacity: Option<u32>,
+
+    /// Number of seconds it takes to refill one token, defaults to 1
+    refill_secs: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BucketConfig {
+    capacity: u32,
+    refill_secs: f64,
+}
+
+impl Default for BucketConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10,
+            refill_secs: 1.0,
+        }
+    }
+}
+
+impl BucketQuery {
+    /// `None` if neither setting was sent, unset ones use the defaults
+    fn config(&self) -> Option<BucketConfig> {
+        if self.capacity.is_none() && self.refill_secs.is_none() {
+            return None;
+        }
+        let default = BucketConfig::default();
+        Some(BucketConfig {
+            capacity: self.capacity.unwrap_or(default.capacity),
+            refill_secs: self.refill_secs.unwrap_or(default.refill_secs),
+        })
+    }
+}
+
+impl BucketConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.capacity == 0 {
+            return Err(anyhow!("capacity must be at least 1"));
+        }
+        if !self.refill_secs.is_finite() || self.refill_secs <= 0.0 {
+            return Err(anyhow!("refill_secs must be a positive number"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    config: BucketConfig,
+    tokens: f64,
+    last_refill: Instant,
+
+    /// When a request last took a token or asked for the status, listing buckets does not count
+    last_used: Instant,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct BucketStatus {
+    bucket: String,
+    limit: u32,
+    remaining: u32,
+    /// Seconds until the bucket is full again
+    reset: u64,
+    /// Seconds until the next token is available, zero if one is available now
+    retry_after: u64,
+}
+
+impl TokenBucket {
+    fn new(config: BucketConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            config,
+            tokens: config.capacity as f64,
+            last_refill: now,
+            last_used: now,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed / self.config.refill_secs).min(self.config.capacity as f64);
+        self.last_refill = now;
+    }
+
+    /// Takes a token if one is available
+    fn try_take(&mut self) -> bool {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn status(&self, name: &str) -> BucketStatus {
+        let missing = self.config.capacity as f64 - self.tokens;
+        BucketStatus {
+            bucket: name.to_string(),
+            limit: self.config.capacity,
+            remaining: self.tokens.floor() as u32,
+            reset: (missing * self.config.refill_secs).ceil() as u64,
+            retry_after: ((1.0 - self.tokens).max(0.0) * self.config.refill_secs).ceil() as u64,
+        }
+    }
+}
+
+impl BucketStatus {
+    /// Adds both the IETF draft `RateLimit-*` headers and the common `X-RateLimit-*` headers
+    fn insert_headers(&self, builder: &mut HttpResponseBuilder) {
+        let reset_at = SystemTime::now()
+            .checked_add(Duration::from_secs(self.reset))
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+        builder
+            .insert_header(("RateLimit-Limit", self.limit))
+            .insert_header(("RateLimit-Remaining", self.remaining))
+            .insert_header(("RateLimit-Reset", self.reset))
+            .insert_header(("X-RateLimit-Limit", self.limit))
+            .insert_header(("X-RateLimit-Remaining", self.remaining))
+            .insert_header(("X-RateLimit-Reset", reset_at));
+    }
+}
+
+/// Takes a token from the bucket, responding with 429 once it is exhausted.
+///
+/// The bucket is created on first use from the query string. Sending a different
+/// configuration for an existing bucket replaces it with a full bucket, requests
+/// without one use the existing bucket as it is. `HEAD`
+/// requests get the same headers without taking a token. At most 1000 buckets are
+/// kept, creating another removes the one used longest ago.
+#[utoipa::path(
+    method(get, post),
+    path = "/ratelimit/{bucket}",
+    tag = "rate limit",
+    params(("bucket" = String, Path, description = "Name of the bucket"), BucketQuery),
+    responses(
+        (status = 200, description = "Token taken", body = BucketStatus),
+        (status = 429, description = "Bucket exhausted, see `Retry-After`", body = BucketStatus),
+        (status = 400, description = "Invalid bucket configuration", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn rate_limit_take(
+    req: HttpRequest,
+    buckets: Data<RateLimitBuckets>,
+    path: Path<String>,
+    Query(query): Query<BucketQuery>,
+) -> crate::Result<HttpResponse> {
+    let name = path.into_inner();
+    let requested = query.config();
+    if let Some(config) = requested.as_ref() {
+        config
+            .validate()
+            .map_err(|e| HandlerError::new(ErrorKind::BadRequest, e))?;
+    }
+    let mut guard = buckets.0.lock().expect("rate limit mutex poisoned");
+    if guard.len() >= MAX_BUCKETS && !guard.contains_key(&name) {
+        let least_recently_used = guard
+            .iter()
+            .min_by_key(|(_, bucket)| bucket.last_used)
+            .map(|(name, _)| name.clone());
+        if let Some(evicted) = least_recently_used {
+            guard.remove(&evicted);
+        }
+    }
+    let bucket = guard
+        .entry(name.clone())
+        .or_insert_with(|| TokenBucket::new(requested.unwrap_or_default()));
+    if let Some(config) = requested.filter(|x| *x != bucket.config) {
+        *bucket = TokenBucket::new(config);
+    }
+    bucket.last_used = Instant::now();
+    let allowed = if is_head(&req) {
+        bucket.refill();
+        bucket.tokens >= 1.0
+    } else {
+        bucket.try_take()
+    };
+    let status = bucket.status(&name);
+    drop(guard);
+
+    let mut builder = if allowed {
+        HttpResponse::Ok()
+    } else {
+        let mut builder = HttpResponse::TooManyRequests();
+        builder.insert_header((RETRY_AFTER, status.retry_after));
+        builder
+    };
+    status.insert_headers(&mut builder);
+    Ok(builder.json(status))
+}
+
+/// Shows the state of a bucket without taking a token
+#[utoipa::path(
+    get,
+    path = "/ratelimit/{bucket}/status",
+    tag = "rate limit",
+    params(("bucket" = String, Path, description = "Name of the bucket")),
+    responses(
+        (status = 200, description = "Current state of the bucket", body = BucketStatus),
+        (status = 404, description = "No bucket with that name", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn rate_limit_status(
+    buckets: Data<RateLimitBuckets>,
+    path: Path<String>,
+) -> crate::Result<HttpResponse> {
+    let name = path.into_inner();
+    let mut guard = buckets.0.lock().expect("rate limit mutex poisoned");
+    let bucket = guard.get_mut(&name).ok_or_else(|| {
+        HandlerError::new(ErrorKind::NotFound, anyhow!("no bucket named: {name}"))
+    })?;
+    bucket.refill();
+    bucket.last_used = Instant::now();
+    let status = bucket.status(&name);
+    let mut builder = HttpResponse::Ok();
+    status.insert_headers(&mut builder);
+    Ok(builder.json(status))
+}
+
+/// Removes a bucket so that the next request starts with a full one
+#[utoipa::path(
+    post,
+    path = "/ratelimit/{bucket}/reset",
+    tag = "rate limit",
+    params(("bucket" = String, Path, description = "Name of the bucket")),
+    responses((status = 200, description = "If a bucket was removed", body = bool))
+)]
+#[instrument]
+pub async fn rate_limit_reset(buckets: Data<RateLimitBuckets>, path: Path<String>) -> Json<bool> {
+    let name = path.into_inner();
+    let removed = buckets
+        .0
+        .lock()
+        .expect("rate limit mutex poisoned")
+        .remove(&name)
+        .is_some();
+    Json(removed)
+}
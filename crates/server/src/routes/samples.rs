@@ -0,0 +1,264 @@
+//! Fixed payloads of many content types used as a reference corpus for response viewers
+//!
+//! Some samples deliberately have a `Content-Type` that does not match the body,
+//! these include the `X-Actual-Content-Type` header to say what the body really is.
+use actix_web::{
+    http::header::CONTENT_TYPE,
+    web::{Json, Path},
+    HttpResponse,
+};
+use anyhow::anyhow;
+use serde::Serialize;
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::{ErrorKind, HandlerError, ProblemDetails};
+
+/// Header with the real type of a sample served with a misleading `Content-Type`
+const ACTUAL_CONTENT_TYPE_HEADER: &str = "X-Actual-Content-Type";
+
+const JSON: &str = "application/json";
+const HTML: &str = "text/html; charset=utf-8";
+const TEXT: &str = "text/plain; charset=utf-8";
+const PNG: &str = "image/png";
+const JPEG: &str = "image/jpeg";
+const OCTET_STREAM: &str = "application/octet-stream";
+
+struct Sample {
+    kind: &'static str,
+    description: &'static str,
+
+    /// Sent as the `Content-Type` header, not sent if `None`
+    content_type: Option<&'static str>,
+
+    /// What the body really is if `content_type` is misleading
+    actual_content_type: Option<&'static str>,
+    body: &'static [u8],
+}
+
+impl Sample {
+    const fn new(
+        kind: &'static str,
+        content_type: &'static str,
+        description: &'static str,
+        body: &'static [u8],
+    ) -> Self {
+        Self {
+            kind,
+            description,
+            content_type: Some(content_type),
+            actual_content_type: None,
+            body,
+        }
+    }
+
+    /// A sample with a `Content-Type` that does not match the body
+    const fn mislabeled(
+        kind: &'static str,
+        content_type: Option<&'static str>,
+        actual_content_type: &'static str,
+        description: &'static str,
+        body: &'static [u8],
+    ) -> Self {
+        Self {
+            kind,
+            description,
+            content_type,
+            actual_content_type: Some(actual_content_type),
+            body,
+        }
+    }
+}
+
+const SAMPLES: &[Sample] = &[
+    Sample::new(
+        "json",
+        JSON,
+        "Object with nested values and unicode",
+        include_bytes!("../../samples/sample.json"),
+    ),
+    Sample::new(
+        "xml",
+        "application/xml",
+        "Document with attributes, entities and CDATA",
+        include_bytes!("../../samples/sample.xml"),
+    ),
+    Sample::new(
+        "html",
+        HTML,
+        "Page with a list, table and link",
+        include_bytes!("../../samples/sample.html"),
+    ),
+    Sample::new(
+        "csv",
+        "text/csv; charset=utf-8",
+        "Table with a header row and quoted fields",
+        include_bytes!("../../samples/sample.csv"),
+    ),
+    Sample::new(
+        "yaml",
+        "application/yaml",
+        "Mapping with a list and multiline string",
+        include_bytes!("../../samples/sample.yaml"),
+    ),
+    Sample::new(
+        "text",
+        TEXT,
+        "Lines with unicode, a tab and trailing spaces",
+        include_bytes!("../../samples/sample.txt"),
+    ),
+    Sample::new(
+        "png",
+        PNG,
+        "16x16 RGB gradient",
+        include_bytes!("../../samples/sample.png"),
+    ),
+    Sample::new(
+        "jpeg",
+        JPEG,
+        "8x8 grey square",
+        include_bytes!("../../samples/sample.jpg"),
+    ),
+    Sample::new(
+        "webp",
+        "image/webp",
+        "1x1 lossless image",
+        include_bytes!("../../samples/sample.webp"),
+    ),
+    Sample::new(
+        "gif",
+        "image/gif",
+        "1x1 transparent image",
+        include_bytes!("../../samples/sample.gif"),
+    ),
+    Sample::new(
+        "svg",
+        "image/svg+xml",
+        "Vector image with shapes and text",
+        include_bytes!("../../samples/sample.svg"),
+    ),
+    Sample::new(
+        "pdf",
+        "application/pdf",
+        "Single page document with one line of text",
+        include_bytes!("../../samples/sample.pdf"),
+    ),
+    Sample::new(
+        "protobuf",
+        "application/x-protobuf",
+        "`Sample` message from `samples/sample.proto`",
+        include_bytes!("../../samples/sample.pb"),
+    ),
+    Sample::new(
+        "octet-stream",
+        OCTET_STREAM,
+        "Every byte value from 0 to 255 in order",
+        include_bytes!("../../samples/sample.bin"),
+    ),
+    Sample::mislabeled(
+        "json-as-text",
+        Some(TEXT),
+        JSON,
+        "JSON sent as plain text",
+        include_bytes!("../../samples/sample.json"),
+    ),
+    Sample::mislabeled(
+        "html-as-json",
+        Some(JSON),
+        HTML,
+        "HTML sent as JSON",
+        include_bytes!("../../samples/sample.html"),
+    ),
+    Sample::mislabeled(
+        "png-as-jpeg",
+        Some(JPEG),
+        PNG,
+        "PNG image sent as JPEG",
+        include_bytes!("../../samples/sample.png"),
+    ),
+    Sample::mislabeled(
+        "text-as-octet-stream",
+        Some(OCTET_STREAM),
+        TEXT,
+        "Text sent as binary",
+        include_bytes!("../../samples/sample.txt"),
+    ),
+    Sample::mislabeled(
+        "latin1-as-utf8",
+        Some(TEXT),
+        "text/plain; charset=iso-8859-1",
+        "Latin-1 text that is not valid UTF-8 sent as UTF-8",
+        include_bytes!("../../samples/latin1.txt"),
+    ),
+    Sample::mislabeled(
+        "no-content-type",
+        None,
+        JSON,
+        "JSON without a `Content-Type` header",
+        include_bytes!("../../samples/sample.json"),
+    ),
+];
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct SampleInfo {
+    kind: &'static str,
+    description: &'static str,
+    content_type: Option<&'static str>,
+
+    /// Set if `content_type` is deliberately wrong
+    actual_content_type: Option<&'static str>,
+
+    /// Size of the body in bytes
+    length: usize,
+}
+
+impl From<&Sample> for SampleInfo {
+    fn from(value: &Sample) -> Self {
+        Self {
+            kind: value.kind,
+            description: value.description,
+            content_type: value.content_type,
+            actual_content_type: value.actual_content_type,
+            length: value.body.len(),
+        }
+    }
+}
+
+/// Lists the available samples
+#[utoipa::path(
+    get,
+    path = "/samples",
+    tag = "samples",
+    responses((status = 200, description = "Samples available at `/samples/{kind}`", body = Vec<SampleInfo>))
+)]
+#[instrument]
+pub async fn samples_list() -> Json<Vec<SampleInfo>> {
+    Json(SAMPLES.iter().map(SampleInfo::from).collect())
+}
+
+/// Returns a sample payload, the same kind always returns the same bytes
+#[utoipa::path(
+    get,
+    path = "/samples/{kind}",
+    tag = "samples",
+    params(("kind" = String, Path, description = "Kind of sample as listed by `/samples`")),
+    responses(
+        (status = 200, description = "The sample with the `Content-Type` listed for it", body = Vec<u8>, content_type = "*/*"),
+        (status = 404, description = "No sample of that kind", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn sample_show(path: Path<String>) -> crate::Result<HttpResponse> {
+    let kind = path.into_inner();
+    let sample = SAMPLES.iter().find(|x| x.kind == kind).ok_or_else(|| {
+        HandlerError::new(ErrorKind::NotFound, anyhow!("no sample of kind: {kind}"))
+    })?;
+    let mut builder = HttpResponse::Ok();
+    if let Some(content_type) = sample.content_type {
+        builder.insert_header((CONTENT_TYPE, content_type));
+    }
+    if let Some(actual) = sample.actual_content_type {
+        builder.insert_header((ACTUAL_CONTENT_TYPE_HEADER, actual));
+    }
+    Ok(builder.body(sample.body))
+}
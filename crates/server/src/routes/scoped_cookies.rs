@@ -0,0 +1,285 @@
+//! Sets cookies scoped to different paths and domains to check which are sent back where
+//!
+//! `/scoped/set` sets every cookie, the pages in [`PAGES`] list the cookies they
+//! receive (like `/cookies/`) and `/scoped/expected` lists what each page should
+//! receive according to RFC 6265 so the two can be compared.
+use std::net::IpAddr;
+
+use actix_web::{
+    http::header::{HeaderValue, SET_COOKIE},
+    web::Json,
+    HttpRequest, HttpResponse,
+};
+use anyhow::Context as _;
+use serde::Serialize;
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use super::cookies::{cookie_show, request_host};
+use crate::ProblemDetails;
+
+/// Pages that list the cookies they receive
+const PAGES: &[&str] = &["/scoped", "/scoped/a", "/scoped/a/b", "/scoped/ab"];
+
+/// Path cookies without a valid `Path` attribute get, the directory of `/scoped/set`
+const DEFAULT_PATH: &str = "/scoped";
+
+/// The `Domain` attribute of a cookie
+#[derive(Debug, Clone, Copy)]
+enum DomainScope {
+    /// No `Domain` attribute
+    HostOnly,
+
+    /// The host the request was sent to
+    Host,
+
+    /// The host without its first label, only set if the host has a parent
+    Parent,
+
+    /// A domain the host is not part of
+    Unrelated,
+}
+
+struct ScopedCookie {
+    name: &'static str,
+
+    /// Value of the `Path` attribute if any
+    path: Option<&'static str>,
+    domain: DomainScope,
+    reason: &'static str,
+}
+
+const COOKIES: &[ScopedCookie] = &[
+    ScopedCookie {
+        name: "path_scoped",
+        path: Some("/scoped"),
+        domain: DomainScope::HostOnly,
+        reason: "sent to every page",
+    },
+    ScopedCookie {
+        name: "path_a",
+        path: Some("/scoped/a"),
+        domain: DomainScope::HostOnly,
+        reason: "sent to `/scoped/a` and below but not to `/scoped/ab`, paths only match whole segments",
+    },
+    ScopedCookie {
+        name: "path_a_slash",
+        path: Some("/scoped/a/"),
+        domain: DomainScope::HostOnly,
+        reason: "sent below `/scoped/a` but not to `/scoped/a` itself because of the trailing slash",
+    },
+    ScopedCookie {
+        name: "path_a_b",
+        path: Some("/scoped/a/b"),
+        domain: DomainScope::HostOnly,
+        reason: "only sent to `/scoped/a/b`, before the cookies with shorter paths",
+    },
+    ScopedCookie {
+        name: "path_ab",
+        path: Some("/scoped/ab"),
+        domain: DomainScope::HostOnly,
+        reason: "only sent to `/scoped/ab`",
+    },
+    ScopedCookie {
+        name: "path_default",
+        path: None,
+        domain: DomainScope::HostOnly,
+        reason: "without a `Path` it defaults to `/scoped`, the directory of `/scoped/set`",
+    },
+    ScopedCookie {
+        name: "path_relative",
+        path: Some("relative"),
+        domain: DomainScope::HostOnly,
+        reason: "a `Path` not starting with `/` is ignored so it defaults to `/scoped`",
+    },
+    ScopedCookie {
+        name: "domain_host",
+        path: Some("/scoped"),
+        domain: DomainScope::Host,
+        reason: "sent to the host and its subdomains, some clients reject IP addresses as a domain",
+    },
+    ScopedCookie {
+        name: "domain_parent",
+        path: Some("/scoped"),
+        domain: DomainScope::Parent,
+        reason: "sent to the parent domain and all its subdomains, not set if the host is an IP address or has fewer than three labels",
+    },
+    ScopedCookie {
+        name: "domain_unrelated",
+        path: Some("/scoped"),
+        domain: DomainScope::Unrelated,
+        reason: "rejected, the domain does not match the host",
+    },
+];
+
+/// Domain used for [`DomainScope::Unrelated`]
+const UNRELATED_DOMAIN: &str = "example.com";
+
+impl ScopedCookie {
+    /// `None` if it is not set for this host
+    fn set_cookie(&self, host: &str) -> Option<String> {
+        let mut result = format!("{}=1", self.name);
+        if let Some(path) = self.path {
+            result.push_str(&format!("; Path={path}"));
+        }
+        match self.domain {
+            DomainScope::HostOnly => {}
+            DomainScope::Host => result.push_str(&format!("; Domain={host}")),
+            DomainScope::Parent => result.push_str(&format!("; Domain={}", parent_domain(host)?)),
+            DomainScope::Unrelated => result.push_str(&format!("; Domain={UNRELATED_DOMAIN}")),
+        }
+        Some(result)
+    }
+
+    fn stored(&self) -> bool {
+        !matches!(self.domain, DomainScope::Unrelated)
+    }
+
+    /// The path the cookie is stored with, see RFC 6265 section 5.2.4
+    fn effective_path(&self) -> &'static str {
+        self.path
+            .filter(|x| x.starts_with('/'))
+            .unwrap_or(DEFAULT_PATH)
+    }
+}
+
+/// Removes the first label if that leaves at least two, as a single label is likely
+/// a top level domain that cookies are not allowed to use
+fn parent_domain(host: &str) -> Option<&str> {
+    if host.starts_with('[') || host.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    let (_, parent) = host.split_once('.')?;
+    parent.contains('.').then_some(parent)
+}
+
+/// Path matching as defined in RFC 6265 section 5.1.4
+fn path_matches(cookie_path: &str, request_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ScopedCookieInfo {
+    name: &'static str,
+
+    /// Value of the `Set-Cookie` header exactly as sent, not set if the host does
+    /// not allow this case
+    set_cookie: Option<String>,
+
+    /// If a client following RFC 6265 should keep it
+    stored: bool,
+    reason: &'static str,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct PageExpectation {
+    path: &'static str,
+
+    /// Cookies the page should list, longer paths first then in the order they were set
+    /// as RFC 6265 recommends, not every client uses this order
+    cookies: Vec<(String, String)>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ScopedExpectations {
+    /// Host the expectations were worked out for as the domain cookies depend on it
+    host: String,
+    cookies: Vec<ScopedCookieInfo>,
+    pages: Vec<PageExpectation>,
+}
+
+impl ScopedExpectations {
+    fn new(host: String) -> Self {
+        let cookies = COOKIES
+            .iter()
+            .map(|cookie| {
+                let set_cookie = cookie.set_cookie(&host);
+                ScopedCookieInfo {
+                    name: cookie.name,
+                    stored: set_cookie.is_some() && cookie.stored(),
+                    set_cookie,
+                    reason: cookie.reason,
+                }
+            })
+            .collect::<Vec<_>>();
+        let pages = PAGES
+            .iter()
+            .map(|&page| {
+                let mut sent: Vec<_> = COOKIES
+                    .iter()
+                    .zip(cookies.iter())
+                    .filter(|(cookie, info)| {
+                        info.stored && path_matches(cookie.effective_path(), page)
+                    })
+                    .map(|(cookie, _)| cookie)
+                    .collect();
+                // Stable so cookies with paths of the same length stay in the order they were set
+                sent.sort_by_key(|x| std::cmp::Reverse(x.effective_path().len()));
+                PageExpectation {
+                    path: page,
+                    cookies: sent
+                        .into_iter()
+                        .map(|x| (x.name.to_string(), "1".to_string()))
+                        .collect(),
+                }
+            })
+            .collect();
+        Self {
+            host,
+            cookies,
+            pages,
+        }
+    }
+}
+
+/// Sets cookies scoped to different paths and domains and lists what each page should receive
+#[utoipa::path(
+    get,
+    path = "/scoped/set",
+    tag = "cookies",
+    responses((status = 200, description = "Cookies set and the expected result for each page", body = ScopedExpectations))
+)]
+#[instrument]
+pub async fn scoped_cookies_set(req: HttpRequest) -> crate::Result<HttpResponse> {
+    let expectations = ScopedExpectations::new(request_host(&req));
+    let mut builder = HttpResponse::Ok();
+    for set_cookie in expectations
+        .cookies
+        .iter()
+        .filter_map(|x| x.set_cookie.as_ref())
+    {
+        let value = HeaderValue::from_str(set_cookie)
+            .with_context(|| format!("invalid Set-Cookie value: {set_cookie:?}"))?;
+        builder.append_header((SET_COOKIE, value));
+    }
+    Ok(builder.json(expectations))
+}
+
+/// Lists the cookies set by `/scoped/set` and the ones each page should receive, without setting them
+#[utoipa::path(
+    get,
+    path = "/scoped/expected",
+    tag = "cookies",
+    responses((status = 200, description = "Expected result for each page", body = ScopedExpectations))
+)]
+#[instrument]
+pub async fn scoped_cookies_expected(req: HttpRequest) -> Json<ScopedExpectations> {
+    Json(ScopedExpectations::new(request_host(&req)))
+}
+
+/// Lists the cookies sent with the request, also served at `/scoped/a`, `/scoped/a/b` and `/scoped/ab`
+#[utoipa::path(
+    get,
+    path = "/scoped",
+    tag = "cookies",
+    responses(
+        (status = 200, description = "Cookies received", body = Vec<(String, String)>),
+        (status = 400, description = "Cookies could not be parsed", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn scoped_cookies_show(req: HttpRequest) -> crate::Result<Json<Vec<(String, String)>>> {
+    cookie_show(req).await
+}
@@ -0,0 +1,196 @@
+//! Based on https://httpbin.org/#/Dynamic_data
+//!
+//! Each JSON object is sent as a separate chunk so clients can test streaming parsers.
+use std::{convert::Infallible, time::Duration};
+
+use actix_web::{
+    http::{
+        header::{ContentType, TRAILER, TRANSFER_ENCODING},
+        Version,
+    },
+    rt::time::sleep,
+    web::{Bytes, Path, Query},
+    HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use futures_util::{stream, StreamExt as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use tracing::instrument;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{ErrorKind, HandlerError, ProblemDetails};
+
+/// Largest number of objects any of these endpoints will produce
+const MAX_OBJECTS: usize = 1000;
+
+/// Longest delay allowed between chunks
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// Content type for newline delimited JSON
+const NDJSON: &str = "application/x-ndjson";
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct NdjsonQuery {
+    /// Milliseconds to wait before sending each chunk after the first
+    delay_ms: Option<u64>,
+}
+
+/// One line of the streamed body
+#[derive(Serialize, ToSchema, Debug)]
+pub struct StreamObject {
+    /// Position of the object starting from 0
+    id: usize,
+
+    /// Number of objects in the body
+    count: usize,
+    url: String,
+}
+
+fn check_count(n: usize) -> crate::Result<()> {
+    if n > MAX_OBJECTS {
+        return Err(HandlerError::new(
+            ErrorKind::BadRequest,
+            anyhow!("at most {MAX_OBJECTS} objects may be requested"),
+        ));
+    }
+    Ok(())
+}
+
+/// The lines of the body, each ending with a newline
+fn lines(req: &HttpRequest, n: usize) -> Vec<Bytes> {
+    (0..n)
+        .map(|id| {
+            let object = StreamObject {
+                id,
+                count: n,
+                url: req.uri().to_string(),
+            };
+            let mut line = serde_json::to_vec(&object).expect("serializing to a Vec cannot fail");
+            line.push(b'\n');
+            Bytes::from(line)
+        })
+        .collect()
+}
+
+/// Sends `lines` as separate chunks waiting `delay` before each one after the first
+fn chunked(mut builder: HttpResponseBuilder, lines: Vec<Bytes>, delay: Duration) -> HttpResponse {
+    let body = stream::iter(lines.into_iter().enumerate()).then(move |(i, line)| async move {
+        if i > 0 && !delay.is_zero() {
+            sleep(delay).await;
+        }
+        Ok::<_, Infallible>(line)
+    });
+    builder.streaming(body)
+}
+
+/// Streams `n` JSON objects, one per line and chunk
+#[utoipa::path(
+    get,
+    path = "/stream/{n}",
+    tag = "stream",
+    params(("n" = usize, Path, description = "Number of objects to send, at most 1000")),
+    responses(
+        (status = 200, description = "One JSON object per line", body = StreamObject),
+        (status = 400, description = "Too many objects requested", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn stream_json(req: HttpRequest, path: Path<usize>) -> crate::Result<HttpResponse> {
+    let n = path.into_inner();
+    check_count(n)?;
+    let mut builder = HttpResponse::Ok();
+    builder.content_type(ContentType::json());
+    Ok(chunked(builder, lines(&req, n), Duration::ZERO))
+}
+
+/// Same as `/stream/{n}` with the SHA-256 of the body in a `Content-Digest` trailer, HTTP/1.1 only
+///
+/// Requests using HTTP/2 (h2c or ALPN) or HTTP/1.0 are rejected with a 400 problem.
+/// actix-web cannot send HTTP/2 trailers and always ends chunked bodies with an empty
+/// trailer section, so the chunk framing and the trailer are written here and
+/// actix-web is told not to add its own.
+#[utoipa::path(
+    get,
+    path = "/stream/{n}/checksum",
+    tag = "stream",
+    params(("n" = usize, Path, description = "Number of objects to send, at most 1000")),
+    responses(
+        (status = 200, description = "One JSON object per line followed by a `Content-Digest` trailer with the `sha-256` digest of the body (RFC 9530)", body = StreamObject,
+            headers(("Trailer" = String, description = "`Content-Digest`"))),
+        (status = 400, description = "Too many objects requested or the request used HTTP/2 or HTTP/1.0, the trailer can only be sent over HTTP/1.1", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn stream_json_checksum(
+    req: HttpRequest,
+    path: Path<usize>,
+) -> crate::Result<HttpResponse> {
+    let n = path.into_inner();
+    check_count(n)?;
+    if req.version() != Version::HTTP_11 {
+        return Err(HandlerError::new(
+            ErrorKind::BadRequest,
+            anyhow!(
+                "trailers need HTTP/1.1 chunked encoding but the request used {:?}",
+                req.version()
+            ),
+        ));
+    }
+    let mut hasher = Sha256::new();
+    let mut chunks: Vec<Bytes> = lines(&req, n)
+        .into_iter()
+        .map(|line| {
+            hasher.update(&line);
+            let mut chunk = format!("{:x}\r\n", line.len()).into_bytes();
+            chunk.extend_from_slice(&line);
+            chunk.extend_from_slice(b"\r\n");
+            Bytes::from(chunk)
+        })
+        .collect();
+    let digest = STANDARD.encode(hasher.finalize());
+    chunks.push(Bytes::from(format!(
+        "0\r\nContent-Digest: sha-256=:{digest}:\r\n\r\n"
+    )));
+    let mut builder = HttpResponse::Ok();
+    builder
+        .content_type(ContentType::json())
+        .insert_header((TRANSFER_ENCODING, "chunked"))
+        .insert_header((TRAILER, "Content-Digest"));
+    let mut resp = chunked(builder, chunks, Duration::ZERO);
+    resp.head_mut().no_chunking(true);
+    Ok(resp)
+}
+
+/// Streams `n` objects as newline delimited JSON, optionally waiting between chunks
+#[utoipa::path(
+    get,
+    path = "/ndjson/{n}",
+    tag = "stream",
+    params(("n" = usize, Path, description = "Number of objects to send, at most 1000"), NdjsonQuery),
+    responses(
+        (status = 200, description = "One JSON object per line", body = StreamObject, content_type = "application/x-ndjson"),
+        (status = 400, description = "Too many objects or too long a delay requested", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument]
+pub async fn ndjson(
+    req: HttpRequest,
+    path: Path<usize>,
+    Query(query): Query<NdjsonQuery>,
+) -> crate::Result<HttpResponse> {
+    let n = path.into_inner();
+    check_count(n)?;
+    let delay = Duration::from_millis(query.delay_ms.unwrap_or_default());
+    if delay > MAX_DELAY {
+        return Err(HandlerError::new(
+            ErrorKind::BadRequest,
+            anyhow!("delay_ms may be at most {}", MAX_DELAY.as_millis()),
+        ));
+    }
+    let mut builder = HttpResponse::Ok();
+    builder.content_type(NDJSON);
+    Ok(chunked(builder, lines(&req, n), delay))
+}
@@ -0,0 +1,61 @@
+//! Sending webhooks to receivers under test, see [`crate::webhooks`]
+use actix_web::{
+    web::{Data, Json},
+    HttpResponse,
+};
+use tracing::instrument;
+
+use crate::{
+    reload::LiveConfig,
+    webhooks::{Delivery, SendWebhook, Webhooks},
+    ErrorKind, HandlerError, ProblemDetails,
+};
+
+/// Schedules a signed webhook, it is sent in the background and retried until accepted
+#[utoipa::path(
+    post,
+    path = "/_webhooks/send",
+    tag = "webhooks",
+    request_body = SendWebhook,
+    responses(
+        (status = 202, description = "The delivery as scheduled, see `/_webhooks/deliveries` for the attempts", body = Delivery),
+        (status = 400, description = "Invalid webhook", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+#[instrument(skip(webhook))]
+pub async fn webhook_send(
+    webhooks: Data<Webhooks>,
+    live: Data<LiveConfig>,
+    webhook: Json<SendWebhook>,
+) -> crate::Result<HttpResponse> {
+    let delivery = webhooks
+        .into_inner()
+        .send(webhook.into_inner(), &live.current().webhooks)
+        .map_err(|e| HandlerError::new(ErrorKind::BadRequest, e))?;
+    Ok(HttpResponse::Accepted().json(delivery))
+}
+
+/// Lists the webhooks sent and the attempts made for each, oldest first
+#[utoipa::path(
+    get,
+    path = "/_webhooks/deliveries",
+    tag = "webhooks",
+    responses((status = 200, description = "Webhooks and their attempts", body = Vec<Delivery>))
+)]
+#[instrument]
+pub async fn webhook_deliveries(webhooks: Data<Webhooks>) -> Json<Vec<Delivery>> {
+    Json(webhooks.list())
+}
+
+/// Removes every delivery from the list, pending ones are still sent
+#[utoipa::path(
+    delete,
+    path = "/_webhooks/deliveries",
+    tag = "webhooks",
+    responses((status = 204, description = "Deliveries removed"))
+)]
+#[instrument]
+pub async fn webhook_deliveries_clear(webhooks: Data<Webhooks>) -> HttpResponse {
+    webhooks.clear();
+    HttpResponse::NoContent().finish()
+}
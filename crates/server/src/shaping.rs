@@ -0,0 +1,241 @@
+//! Simulates poor network conditions for any route
+//!
+//! Conditions are described with comma separated settings, for example
+//! `latency_ms=200, jitter_ms=100, bytes_per_sec=4096, failure_rate=0.1`. They are
+//! taken from the first of these that is set:
+//! 1. The [`SHAPE_HEADER`] request header
+//! 2. The [`SHAPE_QUERY_PARAM`] query parameter
+//! 3. The first rule in the `shaping` config section with a matching prefix
+use std::{fmt, str::FromStr, time::Duration};
+
+use actix_web::{
+    body::{BodySize, BodyStream, BoxBody, EitherBody, MessageBody, SizedStream},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+    rt::time::sleep,
+    web::{Bytes, Data, Query},
+};
+use anyhow::{anyhow, bail, Context as _};
+use futures_util::{stream, StreamExt as _};
+
+use crate::{reload::LiveConfig, ErrorKind, HandlerError};
+
+/// Request header describing the conditions to apply
+pub const SHAPE_HEADER: HeaderName = HeaderName::from_static("x-test-shape");
+
+/// Query parameter describing the conditions to apply, used if the header is not sent
+pub const SHAPE_QUERY_PARAM: &str = "_shape";
+
+/// Response header with the conditions that were applied
+pub const SHAPE_APPLIED_HEADER: HeaderName = HeaderName::from_static("x-test-shape-applied");
+
+/// Longest total delay before a response starts
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Number of pieces sent per second when limiting bandwidth
+const PIECES_PER_SEC: u64 = 10;
+
+/// Network conditions to simulate
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Shape {
+    /// Delay before the request is handled
+    latency: Duration,
+
+    /// Upper bound of a random delay added to `latency`
+    jitter: Duration,
+
+    /// Rate the response body is sent at, unlimited if not set
+    bytes_per_sec: Option<u64>,
+
+    /// Chance from 0 to 1 of responding with a simulated failure instead
+    failure_rate: f64,
+}
+
+impl FromStr for Shape {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut result = Self::default();
+        for setting in s.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let (key, value) = setting
+                .split_once('=')
+                .with_context(|| format!("setting must be `name=value` but found: {setting:?}"))?;
+            let (key, value) = (key.trim(), value.trim());
+            let invalid = || format!("invalid value for {key}: {value:?}");
+            match key {
+                "latency_ms" => {
+                    result.latency = Duration::from_millis(value.parse().with_context(invalid)?)
+                }
+                "jitter_ms" => {
+                    result.jitter = Duration::from_millis(value.parse().with_context(invalid)?)
+                }
+                "bytes_per_sec" => {
+                    let rate: u64 = value.parse().with_context(invalid)?;
+                    if rate == 0 {
+                        bail!("bytes_per_sec must be at least 1");
+                    }
+                    result.bytes_per_sec = Some(rate);
+                }
+                "failure_rate" => {
+                    let rate: f64 = value.parse().with_context(invalid)?;
+                    if !(0.0..=1.0).contains(&rate) {
+                        bail!("failure_rate must be between 0 and 1 but found: {rate}");
+                    }
+                    result.failure_rate = rate;
+                }
+                _ => bail!(
+                    "unknown setting {key:?}, expected latency_ms, jitter_ms, bytes_per_sec or failure_rate"
+                ),
+            }
+        }
+        if result.latency + result.jitter > MAX_DELAY {
+            bail!(
+                "latency_ms plus jitter_ms may be at most {}",
+                MAX_DELAY.as_millis()
+            );
+        }
+        Ok(result)
+    }
+}
+
+/// Only the settings that differ from the defaults in the same format that is parsed
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut settings = vec![];
+        if !self.latency.is_zero() {
+            settings.push(format!("latency_ms={}", self.latency.as_millis()));
+        }
+        if !self.jitter.is_zero() {
+            settings.push(format!("jitter_ms={}", self.jitter.as_millis()));
+        }
+        if let Some(rate) = self.bytes_per_sec {
+            settings.push(format!("bytes_per_sec={rate}"));
+        }
+        if self.failure_rate > 0.0 {
+            settings.push(format!("failure_rate={}", self.failure_rate));
+        }
+        write!(f, "{}", settings.join(","))
+    }
+}
+
+impl Shape {
+    /// `None` if no conditions apply to the request
+    fn of(req: &ServiceRequest) -> Option<crate::Result<Self>> {
+        let config = req.app_data::<Data<LiveConfig>>()?.current();
+        if config.shaping.from_requests {
+            if let Some(value) = req.headers().get(SHAPE_HEADER) {
+                let shape = value
+                    .to_str()
+                    .map_err(|_| anyhow!("{SHAPE_HEADER} must be ASCII"))
+                    .and_then(str::parse)
+                    .with_context(|| format!("invalid {SHAPE_HEADER} header"));
+                return Some(shape.map_err(bad_request));
+            }
+            let query = Query::<Vec<(String, String)>>::from_query(req.query_string()).ok();
+            if let Some((_, value)) = query
+                .iter()
+                .flat_map(|x| x.iter())
+                .find(|(key, _)| key == SHAPE_QUERY_PARAM)
+            {
+                let shape = value
+                    .parse()
+                    .with_context(|| format!("invalid {SHAPE_QUERY_PARAM} query parameter"));
+                return Some(shape.map_err(bad_request));
+            }
+        }
+        let rule = config
+            .shaping
+            .rules
+            .iter()
+            .find(|rule| req.path().starts_with(&rule.prefix))?;
+        // Rules are checked when the config is validated
+        Some(rule.shape.parse().map_err(HandlerError::from))
+    }
+
+    fn delay(&self) -> Duration {
+        let jitter = fastrand::u64(0..=self.jitter.as_millis() as u64);
+        self.latency + Duration::from_millis(jitter)
+    }
+
+    fn fails(&self) -> bool {
+        self.failure_rate > 0.0 && fastrand::f64() < self.failure_rate
+    }
+}
+
+fn bad_request(error: anyhow::Error) -> HandlerError {
+    HandlerError::new(ErrorKind::BadRequest, error)
+}
+
+/// Middleware that applies the [`Shape`] for the request, if any
+pub async fn shape_responses<B: MessageBody + 'static>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> actix_web::Result<ServiceResponse<EitherBody<B>>> {
+    let shape = match Shape::of(&req) {
+        None => return Ok(next.call(req).await?.map_into_left_body()),
+        Some(Err(err)) => return Ok(req.error_response(err).map_into_right_body()),
+        Some(Ok(shape)) => shape,
+    };
+    let applied = HeaderValue::from_str(&shape.to_string()).ok();
+    sleep(shape.delay()).await;
+    let mut res = if shape.fails() {
+        let err = HandlerError::new(
+            ErrorKind::SimulatedFailure,
+            anyhow!("failed at random with failure_rate={}", shape.failure_rate),
+        );
+        req.error_response(err).map_into_right_body()
+    } else {
+        let res = next.call(req).await?;
+        match shape.bytes_per_sec {
+            Some(rate) => res
+                .map_body(|_, body| throttle(body, rate))
+                .map_into_right_body(),
+            None => res.map_into_left_body(),
+        }
+    };
+    if let Some(applied) = applied {
+        res.headers_mut().insert(SHAPE_APPLIED_HEADER, applied);
+    }
+    Ok(res)
+}
+
+/// Sends `body` in small pieces spaced out to average `bytes_per_sec`
+fn throttle<B: MessageBody + 'static>(body: B, bytes_per_sec: u64) -> BoxBody {
+    let size = body.size();
+    if matches!(size, BodySize::None) {
+        return BoxBody::new(body);
+    }
+    let piece_size = (bytes_per_sec / PIECES_PER_SEC).max(1) as usize;
+    let mut body = Box::pin(body);
+    let pieces = stream::poll_fn(move |cx| body.as_mut().poll_next(cx))
+        .flat_map(move |chunk| {
+            let pieces: Vec<_> = match chunk {
+                Ok(bytes) => split(bytes, piece_size).into_iter().map(Ok).collect(),
+                Err(err) => vec![Err(err.into())],
+            };
+            stream::iter(pieces)
+        })
+        .then(
+            move |piece: Result<Bytes, Box<dyn std::error::Error>>| async move {
+                if let Ok(bytes) = piece.as_ref() {
+                    sleep(Duration::from_secs_f64(
+                        bytes.len() as f64 / bytes_per_sec as f64,
+                    ))
+                    .await;
+                }
+                piece
+            },
+        );
+    match size {
+        BodySize::Sized(len) => BoxBody::new(SizedStream::new(len, pieces)),
+        _ => BoxBody::new(BodyStream::new(pieces)),
+    }
+}
+
+fn split(bytes: Bytes, piece_size: usize) -> Vec<Bytes> {
+    (0..bytes.len())
+        .step_by(piece_size)
+        .map(|start| bytes.slice(start..(start + piece_size).min(bytes.len())))
+        .collect()
+}
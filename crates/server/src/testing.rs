@@ -0,0 +1,101 @@
+//! Runs the server in process so it can be used as a fake server in tests
+//!
+//! Needs the `testing` feature, usually enabled in `[dev-dependencies]`:
+//! `http-test-server = { ..., features = ["testing"] }`.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! let server = http_test_server::testing::TestServer::start()?;
+//! let _resp = server.client().get(server.url("/cookies/")).send().await;
+//! # Ok(())
+//! # }
+//! ```
+use std::net::{SocketAddr, TcpListener};
+
+use actix_web::dev::ServerHandle;
+use tokio::task::JoinHandle;
+
+use crate::ServerConfig;
+
+/// Server listening on an ephemeral port of the loopback interface, stopped when dropped
+pub struct TestServer {
+    addr: SocketAddr,
+    handle: ServerHandle,
+    client: reqwest::Client,
+
+    /// Serving native gRPC on `grpc_addrs`
+    grpc: Vec<JoinHandle<anyhow::Result<()>>>,
+    grpc_addrs: Vec<SocketAddr>,
+}
+
+impl TestServer {
+    /// Starts the server using the default config, must be called from within a tokio runtime
+    pub fn start() -> anyhow::Result<Self> {
+        Self::start_with_config(ServerConfig::default())
+    }
+
+    /// Starts the server using `config`, must be called from within a tokio runtime
+    ///
+    /// Defaults to a single worker if the number of workers is not set.
+    pub fn start_with_config(mut config: ServerConfig) -> anyhow::Result<Self> {
+        config.validate()?;
+        config.server.workers.get_or_insert(1);
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let grpc_listeners = crate::bind_grpc(&config)?;
+        let grpc_addrs = grpc_listeners
+            .iter()
+            .map(TcpListener::local_addr)
+            .collect::<Result<_, _>>()?;
+        let (server, grpc) = crate::create_server(config, vec![listener], grpc_listeners)?;
+        let handle = server.handle();
+        tokio::spawn(server);
+        let client = reqwest::Client::builder().cookie_store(true).build()?;
+        Ok(Self {
+            addr,
+            handle,
+            client,
+            grpc,
+            grpc_addrs,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Where native gRPC is served, in the order of `grpc.listen` with any port 0 resolved
+    pub fn grpc_addrs(&self) -> &[SocketAddr] {
+        &self.grpc_addrs
+    }
+
+    /// The URL of the server without a trailing slash (for example `http://127.0.0.1:12345`)
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// The full URL for `path` which should start with a `/`
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url())
+    }
+
+    /// A client that stores cookies across requests
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// Stops the server waiting for in-flight requests to finish
+    pub async fn stop(self) {
+        self.handle.stop(true).await;
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        // Stopping is idempotent so it does not matter if `stop` was already called
+        drop(self.handle.stop(false));
+        for task in self.grpc.iter() {
+            task.abort();
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! Outbound webhooks for testing receivers, sent in the background with retries
+//!
+//! Each webhook is a `POST` of a JSON payload signed with HMAC-SHA256 the way popular
+//! providers sign theirs:
+//! - `X-Signature-256: sha256=<hex>` over the body, like GitHub
+//! - `X-Signature: t=<timestamp>,v1=<hex>` over `<timestamp>.<body>`, like Stripe
+//!
+//! Every attempt sends the same body and signatures so receivers can check idempotency
+//! with `X-Webhook-Id`. Only `http://` receivers are supported.
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::rt::{self, time::sleep};
+use anyhow::{anyhow, Context as _};
+use hmac::{Hmac, Mac as _};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE},
+    redirect,
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::config::WebhooksConfig;
+
+/// Longest wait before the first attempt or between attempts
+const MAX_DELAY: Duration = Duration::from_secs(3600);
+
+/// A webhook to send, the optional fields default to the `webhooks` config section
+#[derive(Deserialize, ToSchema, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SendWebhook {
+    /// `http://` URL of the receiver
+    url: String,
+
+    /// Sent in the `X-Webhook-Event` header
+    #[serde(default)]
+    event: Option<String>,
+
+    /// Sent as the JSON body
+    #[serde(default)]
+    payload: serde_json::Value,
+
+    /// Milliseconds to wait before the first attempt
+    #[serde(default)]
+    delay_ms: u64,
+
+    /// Unix time in seconds used in the signature, defaults to now
+    #[serde(default)]
+    timestamp: Option<u64>,
+
+    #[serde(default)]
+    secret: Option<String>,
+
+    #[serde(default)]
+    max_attempts: Option<u32>,
+
+    #[serde(default)]
+    backoff_ms: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryState {
+    /// Waiting for the first attempt or a retry
+    Pending,
+
+    /// The receiver responded with a 2xx status
+    Delivered,
+
+    /// Every attempt failed
+    Failed,
+}
+
+/// A webhook and the attempts made to send it
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct Delivery {
+    /// Increases with each webhook, also sent in `X-Webhook-Id`
+    id: u64,
+    url: String,
+    event: Option<String>,
+    state: DeliveryState,
+
+    /// Milliseconds since the Unix epoch when the webhook was scheduled
+    created_at_ms: u64,
+
+    /// Headers sent with every attempt, except for `X-Webhook-Attempt`
+    headers: BTreeMap<String, String>,
+    body: String,
+    max_attempts: u32,
+    attempts: Vec<DeliveryAttempt>,
+}
+
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct DeliveryAttempt {
+    /// Starts at 1
+    attempt: u32,
+
+    /// Milliseconds since the Unix epoch when the request was sent
+    started_at_ms: u64,
+
+    /// Milliseconds until the receiver responded
+    duration_ms: u64,
+
+    /// Not set if the receiver could not be reached
+    status: Option<u16>,
+
+    /// Why the attempt failed
+    error: Option<String>,
+}
+
+/// The HTTP client used to send webhooks and the deliveries so far, shared by all workers
+#[derive(Debug)]
+pub struct Webhooks {
+    client: reqwest::Client,
+    last_id: AtomicU64,
+    deliveries: Mutex<VecDeque<Delivery>>,
+}
+
+/// What is needed to send a delivery after it has been recorded
+struct Schedule {
+    id: u64,
+    url: String,
+    headers: HeaderMap,
+    body: String,
+    max_attempts: u32,
+    delay: Duration,
+    backoff: Duration,
+    timeout: Duration,
+}
+
+impl Default for Webhooks {
+    fn default() -> Self {
+        Self {
+            // Redirects are treated as failures, receivers are expected to respond directly
+            client: reqwest::Client::builder()
+                .redirect(redirect::Policy::none())
+                .build()
+                .expect("failed to build webhook HTTP client"),
+            last_id: Default::default(),
+            deliveries: Default::default(),
+        }
+    }
+}
+
+impl Webhooks {
+    /// Oldest first
+    pub fn list(&self) -> Vec<Delivery> {
+        self.lock().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Records the delivery and sends it in the background
+    pub fn send(
+        self: Arc<Self>,
+        webhook: SendWebhook,
+        config: &WebhooksConfig,
+    ) -> anyhow::Result<Delivery> {
+        let url = reqwest::Url::parse(&webhook.url)
+            .with_context(|| format!("invalid url: {:?}", webhook.url))?;
+        if url.scheme() != "http" {
+            return Err(anyhow!(
+                "only http:// receivers are supported but found: {:?}",
+                webhook.url
+            ));
+        }
+        let max_attempts = webhook.max_attempts.unwrap_or(config.max_attempts);
+        if max_attempts == 0 {
+            return Err(anyhow!("max_attempts must be greater than 0"));
+        }
+        let secret = webhook.secret.as_deref().unwrap_or(&config.secret);
+        if secret.is_empty() {
+            return Err(anyhow!("secret must not be empty"));
+        }
+
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let timestamp = webhook.timestamp.unwrap_or(created_at.as_secs());
+        let body = serde_json::to_string(&webhook.payload).context("failed to encode payload")?;
+        let id = self.last_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let headers = signed_headers(id, webhook.event.as_deref(), timestamp, &body, secret)?;
+        let delivery = Delivery {
+            id,
+            url: url.to_string(),
+            event: webhook.event,
+            state: DeliveryState::Pending,
+            created_at_ms: created_at.as_millis() as u64,
+            headers: headers
+                .iter()
+                .map(|(name, value)| {
+                    let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+                    (name.to_string(), value)
+                })
+                .collect(),
+            body: body.clone(),
+            max_attempts,
+            attempts: vec![],
+        };
+        self.push(delivery.clone(), config.delivery_limit);
+
+        let schedule = Schedule {
+            id,
+            url: url.to_string(),
+            headers,
+            body,
+            max_attempts,
+            delay: Duration::from_millis(webhook.delay_ms).min(MAX_DELAY),
+            backoff: Duration::from_millis(webhook.backoff_ms.unwrap_or(config.backoff_ms)),
+            timeout: Duration::from_millis(config.timeout_ms),
+        };
+        rt::spawn(async move { self.deliver(schedule).await });
+        Ok(delivery)
+    }
+
+    /// Makes attempts until the receiver accepts the webhook or `max_attempts` is reached
+    async fn deliver(&self, schedule: Schedule) {
+        sleep(schedule.delay).await;
+        for attempt in 1..=schedule.max_attempts {
+            let started_at = SystemTime::now();
+            let start = Instant::now();
+            let result = self
+                .client
+                .post(&schedule.url)
+                .headers(schedule.headers.clone())
+                .header("x-webhook-attempt", attempt)
+                .timeout(schedule.timeout)
+                .body(schedule.body.clone())
+                .send()
+                .await;
+            let (status, error) = match result {
+                Ok(resp) if resp.status().is_success() => (Some(resp.status().as_u16()), None),
+                Ok(resp) => (
+                    Some(resp.status().as_u16()),
+                    Some(format!("receiver responded with {}", resp.status())),
+                ),
+                Err(err) => (None, Some(format!("{:#}", anyhow::Error::from(err)))),
+            };
+            let state = match error {
+                None => DeliveryState::Delivered,
+                Some(_) if attempt == schedule.max_attempts => DeliveryState::Failed,
+                Some(_) => DeliveryState::Pending,
+            };
+            match error.as_deref() {
+                None => info!(id = schedule.id, attempt, "webhook delivered"),
+                Some(error) => warn!(id = schedule.id, attempt, error, "webhook attempt failed"),
+            }
+            self.update(schedule.id, |delivery| {
+                delivery.state = state;
+                delivery.attempts.push(DeliveryAttempt {
+                    attempt,
+                    started_at_ms: started_at
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64,
+                    duration_ms: start.elapsed().as_millis() as u64,
+                    status,
+                    error,
+                });
+            });
+            if state != DeliveryState::Pending {
+                return;
+            }
+            let backoff = schedule.backoff.saturating_mul(1 << (attempt - 1).min(20));
+            sleep(backoff.min(MAX_DELAY)).await;
+        }
+    }
+
+    /// Drops the oldest deliveries beyond `limit`
+    fn push(&self, delivery: Delivery, limit: usize) {
+        let mut deliveries = self.lock();
+        deliveries.push_back(delivery);
+        while deliveries.len() > limit {
+            deliveries.pop_front();
+        }
+    }
+
+    /// Does nothing if the delivery was already dropped or cleared
+    fn update(&self, id: u64, f: impl FnOnce(&mut Delivery)) {
+        if let Some(delivery) = self.lock().iter_mut().find(|x| x.id == id) {
+            f(delivery);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Delivery>> {
+        self.deliveries
+            .lock()
+            .expect("webhook deliveries mutex poisoned")
+    }
+}
+
+/// Lowercase hex of the HMAC-SHA256 of `message`
+fn sign(secret: &str, message: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(message.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect()
+}
+
+fn signed_headers(
+    id: u64,
+    event: Option<&str>,
+    timestamp: u64,
+    body: &str,
+    secret: &str,
+) -> anyhow::Result<HeaderMap> {
+    let mut result = HeaderMap::new();
+    result.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let mut values = vec![
+        ("x-webhook-id", id.to_string()),
+        ("x-webhook-timestamp", timestamp.to_string()),
+        ("x-signature-256", format!("sha256={}", sign(secret, body))),
+        (
+            "x-signature",
+            format!(
+                "t={timestamp},v1={}",
+                sign(secret, &format!("{timestamp}.{body}"))
+            ),
+        ),
+    ];
+    if let Some(event) = event {
+        values.push(("x-webhook-event", event.to_string()));
+    }
+    for (name, value) in values {
+        let value = HeaderValue::from_str(&value)
+            .with_context(|| format!("invalid value for {name}: {value:?}"))?;
+        result.insert(HeaderName::from_static(name), value);
+    }
+    Ok(result)
+}
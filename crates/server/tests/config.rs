@@ -0,0 +1,124 @@
+use http_test_server::{config::LogFormat, ServerConfig};
+
+#[test]
+fn example_config_matches_defaults() {
+    let config = ServerConfig::from_toml(include_str!("../http_test.example.toml")).unwrap();
+    assert_eq!(config, ServerConfig::default());
+}
+
+#[test]
+fn partial_config_keeps_other_defaults() {
+    let config = ServerConfig::from_toml(
+        r#"
+        [routes]
+        echo = false
+
+        [logging]
+        format = "compact"
+        "#,
+    )
+    .unwrap();
+    assert!(!config.routes.echo);
+    assert!(config.routes.cookies);
+    assert_eq!(config.logging.format, LogFormat::Compact);
+    assert_eq!(config.static_files, Default::default());
+}
+
+#[test]
+fn env_vars_override_known_sections_only() {
+    // No other test in this file reads the environment
+    std::env::set_var("HTTP_TEST_STATIC_FILES__DIR", "public");
+    std::env::set_var("HTTP_TEST_ROUTES__ECHO", "false");
+    std::env::set_var("HTTP_TEST_UNRELATED_TOOL", "1");
+    let path = std::env::temp_dir().join(format!("http_test_env_vars_{}.toml", std::process::id()));
+    std::fs::write(&path, "").unwrap();
+    let result = ServerConfig::load_from(&path);
+    std::fs::remove_file(&path).unwrap();
+    for name in [
+        "HTTP_TEST_STATIC_FILES__DIR",
+        "HTTP_TEST_ROUTES__ECHO",
+        "HTTP_TEST_UNRELATED_TOOL",
+    ] {
+        std::env::remove_var(name);
+    }
+    let config = result.unwrap();
+    assert_eq!(config.static_files.dir, std::path::PathBuf::from("public"));
+    assert!(!config.routes.echo);
+    assert!(config.routes.cookies);
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+    let err = ServerConfig::from_toml("[static_files]\ndirectory = \"public\"").unwrap_err();
+    assert!(format!("{err:#}").contains("directory"), "{err:#}");
+}
+
+#[test]
+fn validation_reports_every_problem() {
+    let err = ServerConfig::from_toml(
+        r#"
+        [tls]
+        listen = []
+        cert_file = "does/not/exist.pem"
+        key_file = "does/not/exist.key"
+
+        [limits]
+        json = 0
+        "#,
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(err.contains("tls.listen"), "{err}");
+    assert!(err.contains("tls.cert_file"), "{err}");
+    assert!(err.contains("tls.key_file"), "{err}");
+    assert!(err.contains("limits.json"), "{err}");
+}
+
+#[test]
+fn strict_cors_requires_valid_origins() {
+    let err = ServerConfig::from_toml(
+        r#"
+        [cors]
+        permissive = false
+        allowed_origins = ["example.com"]
+        "#,
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(err.contains("cors.allowed_origins"), "{err}");
+}
+
+#[test]
+fn proxy_rules_are_validated() {
+    let err = ServerConfig::from_toml(
+        r#"
+        [proxy.reverse]
+        prefix = "app"
+        upstream = "https://example.com"
+
+        [[proxy.rewrite]]
+        direction = "request"
+        add_headers = ["missing colon"]
+        "#,
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(err.contains("proxy.reverse.prefix"), "{err}");
+    assert!(err.contains("proxy.reverse.upstream"), "{err}");
+    assert!(err.contains("proxy.rewrite.add_headers"), "{err}");
+}
+
+#[test]
+fn shaping_rules_are_validated() {
+    let err = ServerConfig::from_toml(
+        r#"
+        [[shaping.rules]]
+        prefix = "/echo"
+        shape = "failure_rate=2"
+        "#,
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(err.contains("shaping.rules"), "{err}");
+    assert!(err.contains("failure_rate"), "{err}");
+}
@@ -0,0 +1,2426 @@
+use http_test_server::{testing::TestServer, ServerConfig};
+use reqwest::{header, StatusCode};
+
+async fn cookies(server: &TestServer) -> Vec<(String, String)> {
+    server
+        .client()
+        .get(server.url("/cookies/"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+fn pair(name: &str, value: &str) -> (String, String) {
+    (name.to_string(), value.to_string())
+}
+
+#[tokio::test]
+async fn echo_parses_json() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .post(server.url("/echo/some/path"))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(r#"{"key":"json_value"}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.text().await.unwrap();
+    assert!(body.starts_with("ECHO RESPONSE"));
+    assert!(body.contains("/echo/some/path"));
+    assert!(body.contains("json_value"));
+}
+
+#[tokio::test]
+async fn echo_parses_form() {
+    let server = TestServer::start().unwrap();
+    let body = server
+        .client()
+        .post(server.url("/echo"))
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body("key=form_value")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("form_value"));
+}
+
+const MULTIPART_BODY: &str = "--boundary\r\n\
+Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+Content-Type: text/plain\r\n\r\n\
+hello\r\n\
+--boundary\r\n\
+Content-Disposition: form-data; name=\"key\"\r\n\r\n\
+value\r\n\
+--boundary--\r\n";
+
+#[tokio::test]
+async fn echo_parses_multipart() {
+    let server = TestServer::start().unwrap();
+    let body = server
+        .client()
+        .post(server.url("/echo"))
+        .header(
+            header::CONTENT_TYPE,
+            "multipart/form-data; boundary=boundary",
+        )
+        .body(MULTIPART_BODY)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        body.contains("name: file, filename: a.txt, content type: text/plain, size: 5 bytes"),
+        "{body}"
+    );
+    assert!(
+        body.contains("name: key, filename: -, content type: -, size: 5 bytes"),
+        "{body}"
+    );
+}
+
+#[tokio::test]
+async fn echo_ignores_bodies_of_other_content_types() {
+    let server = TestServer::start().unwrap();
+    let requests = [
+        server.client().get(server.url("/echo")),
+        server
+            .client()
+            .post(server.url("/echo"))
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body("key=form_value"),
+        server
+            .client()
+            .post(server.url("/echo"))
+            .header(
+                header::CONTENT_TYPE,
+                "multipart/form-data; boundary=boundary",
+            )
+            .body(MULTIPART_BODY),
+    ];
+    for request in requests {
+        let body = request.send().await.unwrap().text().await.unwrap();
+        assert!(body.contains("-- errors --\n[]"), "{body}");
+    }
+}
+
+#[tokio::test]
+async fn echo_reports_body_errors() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .post(server.url("/echo"))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body("{not json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.text().await.unwrap();
+    assert!(body.contains("\"json: Json deserialize error:"), "{body}");
+}
+
+#[tokio::test]
+async fn bodies_over_the_limits_are_rejected() {
+    let mut config = ServerConfig::default();
+    config.limits.json = 8;
+    config.limits.form = 8;
+    config.limits.multipart = 8;
+    config.limits.payload = 8;
+    let server = TestServer::start_with_config(config).unwrap();
+    for (path, content_type, body, detail) in [
+        (
+            "/echo",
+            "application/json",
+            r#"{"key":"value"}"#,
+            "limit: 8 bytes",
+        ),
+        (
+            "/echo",
+            "application/x-www-form-urlencoded",
+            "key=value",
+            "limit: 8 bytes",
+        ),
+        (
+            "/echo",
+            "multipart/form-data; boundary=boundary",
+            MULTIPART_BODY,
+            "multipart body",
+        ),
+        ("/echo_raw", "text/plain", "some text", "size limit"),
+    ] {
+        let resp = server
+            .client()
+            .post(server.url(path))
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            resp.status(),
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "{content_type}"
+        );
+        let problem: serde_json::Value = resp.json().await.unwrap();
+        let actual = problem["detail"].as_str().unwrap();
+        assert!(actual.contains(detail), "{content_type}: {actual}");
+    }
+}
+
+#[tokio::test]
+async fn echo_raw_returns_body() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .put(server.url("/echo_raw"))
+        .body("raw body bytes")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.text().await.unwrap();
+    assert!(body.starts_with("ECHO RAW RESPONSE"));
+    assert!(body.contains("raw body bytes"));
+}
+
+#[tokio::test]
+async fn cookies_start_empty() {
+    let server = TestServer::start().unwrap();
+    assert!(cookies(&server).await.is_empty());
+}
+
+#[tokio::test]
+async fn cookie_set_redirects_to_show() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/cookies/set/name/value"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.url().path(), "/cookies/");
+    let shown: Vec<(String, String)> = resp.json().await.unwrap();
+    assert_eq!(shown, vec![pair("name", "value")]);
+}
+
+#[tokio::test]
+async fn cookie_set_with_stay() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/cookies/set/name/value?stay"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.text().await.unwrap(), "set cookie: name = value");
+    assert_eq!(cookies(&server).await, vec![pair("name", "value")]);
+}
+
+#[tokio::test]
+async fn cookie_delete_redirects_to_show() {
+    let server = TestServer::start().unwrap();
+    server
+        .client()
+        .get(server.url("/cookies/set/name/value?stay"))
+        .send()
+        .await
+        .unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/cookies/delete/name"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.url().path(), "/cookies/");
+    let shown: Vec<(String, String)> = resp.json().await.unwrap();
+    assert!(shown.is_empty());
+}
+
+#[tokio::test]
+async fn cookie_delete_with_stay() {
+    let server = TestServer::start().unwrap();
+    server
+        .client()
+        .get(server.url("/cookies/set/name/value?stay"))
+        .send()
+        .await
+        .unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/cookies/delete/name?stay"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.text().await.unwrap(), "removed cookie: name");
+    assert!(cookies(&server).await.is_empty());
+}
+
+#[tokio::test]
+async fn not_found_is_problem_json() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/does/not/exist"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        resp.headers()[header::CONTENT_TYPE],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(problem["status"], 404);
+    assert_eq!(problem["title"], "Not Found");
+    assert!(problem["request_id"].is_string());
+}
+
+#[tokio::test]
+async fn invalid_parameters_are_problem_json() {
+    let server = TestServer::start().unwrap();
+    for path in [
+        "/bytes/1000000",
+        "/range/1000000",
+        "/stream-bytes/1000000",
+        "/stream/5000",
+        "/stream/5000/checksum",
+        "/ndjson/5000",
+        "/ndjson/1?delay_ms=60000",
+    ] {
+        let resp = server.client().get(server.url(path)).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{path}");
+        assert_eq!(
+            resp.headers()[header::CONTENT_TYPE],
+            "application/problem+json",
+            "{path}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn missing_static_file_is_not_found() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/missing.js"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn static_files_served_from_configured_dir() {
+    let dir = std::env::temp_dir().join(format!("http_test_static_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("index.html"), "<p>static index</p>").unwrap();
+    let mut config = ServerConfig::default();
+    config.static_files.dir = dir.clone();
+    let server = TestServer::start_with_config(config).unwrap();
+
+    let resp = server.client().get(server.url("/")).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.text().await.unwrap(), "<p>static index</p>");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn disabled_route_group_is_not_found() {
+    let mut config = ServerConfig::default();
+    config.routes.echo = false;
+    let server = TestServer::start_with_config(config).unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/echo"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn range_returns_partial_content() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/range/26"))
+        .header(header::RANGE, "bytes=2-4")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(resp.headers()[header::CONTENT_RANGE], "bytes 2-4/26");
+    assert_eq!(resp.text().await.unwrap(), "cde");
+}
+
+#[tokio::test]
+async fn bytes_are_reproducible() {
+    let server = TestServer::start().unwrap();
+    let get = |path: &'static str| async {
+        server
+            .client()
+            .get(server.url(path))
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap()
+    };
+    let bytes = get("/bytes/100?seed=7").await;
+    assert_eq!(bytes.len(), 100);
+    assert_eq!(bytes, get("/stream-bytes/100?seed=7&chunk_size=7").await);
+}
+
+#[tokio::test]
+async fn rate_limit_exhausts_bucket() {
+    let server = TestServer::start().unwrap();
+    let url = server.url("/ratelimit/test?capacity=1&refill_secs=60");
+    let first = server.client().get(&url).send().await.unwrap();
+    assert_eq!(first.status(), StatusCode::OK);
+    let second = server.client().get(&url).send().await.unwrap();
+    assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(second.headers().contains_key(header::RETRY_AFTER));
+    assert_eq!(second.headers()["RateLimit-Remaining"], "0");
+    let without_config = server
+        .client()
+        .get(server.url("/ratelimit/test"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        without_config.status(),
+        StatusCode::TOO_MANY_REQUESTS,
+        "existing bucket kept"
+    );
+    assert_eq!(without_config.headers()["RateLimit-Limit"], "1");
+
+    let missing = server
+        .client()
+        .get(server.url("/ratelimit/missing/status"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        missing.headers()[header::CONTENT_TYPE],
+        "application/problem+json"
+    );
+}
+
+#[tokio::test]
+async fn rate_limit_evicts_least_recently_used_bucket() {
+    let server = TestServer::start().unwrap();
+    let take = |bucket: String| {
+        let request = server
+            .client()
+            .get(server.url(&format!("/ratelimit/{bucket}")));
+        async move { request.send().await.unwrap().status() }
+    };
+    assert_eq!(take("oldest".to_string()).await, StatusCode::OK);
+    assert_eq!(take("kept".to_string()).await, StatusCode::OK);
+    for i in 0..998 {
+        take(format!("bucket-{i}")).await;
+    }
+    // Using the bucket again makes "kept" more recent than "oldest"
+    take("kept".to_string()).await;
+    assert_eq!(take("newest".to_string()).await, StatusCode::OK);
+    for (bucket, expected) in [
+        ("oldest", StatusCode::NOT_FOUND),
+        ("kept", StatusCode::OK),
+        ("newest", StatusCode::OK),
+    ] {
+        let resp = server
+            .client()
+            .get(server.url(&format!("/ratelimit/{bucket}/status")))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), expected, "{bucket}");
+    }
+}
+
+#[tokio::test]
+async fn rate_limit_options_and_head_keep_tokens() {
+    let server = TestServer::start().unwrap();
+    let url = server.url("/ratelimit/safe?capacity=1&refill_secs=60");
+    let resp = server
+        .client()
+        .request(reqwest::Method::OPTIONS, &url)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert_eq!(resp.headers()[header::ALLOW], "GET, POST, HEAD, OPTIONS");
+    let resp = server.client().delete(&url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    let resp = server.client().head(&url).send().await.unwrap();
+    assert_eq!(resp.headers()["RateLimit-Remaining"], "1");
+    let resp = server.client().get(&url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["RateLimit-Remaining"], "0");
+}
+
+#[tokio::test]
+async fn health_endpoints_respond() {
+    let server = TestServer::start().unwrap();
+    for path in [
+        "/healthz",
+        "/readyz",
+        "/version",
+        "/metrics",
+        "/openapi.json",
+    ] {
+        let resp = server.client().get(server.url(path)).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK, "{path}");
+    }
+}
+
+/// Every route that should be in the OpenAPI spec with the methods it accepts
+///
+/// Left out as they are only other paths to a documented handler: `/anything/{_}`,
+/// `/echo/{_}`, `/echo_raw/{_}` and the `/scoped` subpaths. The docs themselves and the
+/// static files are not described either.
+const DOCUMENTED_ROUTES: &[(&str, &[&str])] = &[
+    ("/_admin/reload", &["POST"]),
+    ("/_dashboard", &["GET"]),
+    ("/_dashboard/events", &["GET"]),
+    ("/_proxy/captures", &["DELETE", "GET"]),
+    ("/_webhooks/deliveries", &["DELETE", "GET"]),
+    ("/_webhooks/send", &["POST"]),
+    ("/anything", &["DELETE", "GET", "PATCH", "POST", "PUT"]),
+    ("/bytes/{n}", &["GET"]),
+    ("/cookies/", &["GET"]),
+    ("/cookies/delete/{name}", &["GET"]),
+    ("/cookies/set/{name}/{value}", &["GET"]),
+    ("/cookies/stress", &["GET"]),
+    ("/cookies/stress/{variant}", &["GET"]),
+    ("/echo", &["DELETE", "GET", "PATCH", "POST", "PUT"]),
+    ("/echo_raw", &["DELETE", "GET", "PATCH", "POST", "PUT"]),
+    ("/encoding", &["GET"]),
+    ("/encoding/{charset}", &["GET"]),
+    ("/graphql", &["GET", "POST"]),
+    ("/graphql/echo", &["GET", "POST"]),
+    ("/healthz", &["GET"]),
+    ("/http_test.echo.Echo/{method}", &["POST"]),
+    ("/metrics", &["GET"]),
+    ("/ndjson/{n}", &["GET"]),
+    ("/protocol", &["GET"]),
+    ("/range/{n}", &["GET"]),
+    ("/ratelimit/{bucket}", &["GET", "POST"]),
+    ("/ratelimit/{bucket}/reset", &["POST"]),
+    ("/ratelimit/{bucket}/status", &["GET"]),
+    ("/readyz", &["GET"]),
+    ("/response", &["DELETE", "GET", "PATCH", "POST", "PUT"]),
+    ("/response-headers", &["GET"]),
+    ("/samples", &["GET"]),
+    ("/samples/{kind}", &["GET"]),
+    ("/scoped", &["GET"]),
+    ("/scoped/expected", &["GET"]),
+    ("/scoped/set", &["GET"]),
+    ("/stream-bytes/{n}", &["GET"]),
+    ("/stream/{n}", &["GET"]),
+    ("/stream/{n}/checksum", &["GET"]),
+    ("/version", &["GET"]),
+];
+
+#[tokio::test]
+async fn openapi_spec_matches_registered_routes() {
+    let mut config = ServerConfig::default();
+    config.routes.admin = true;
+    config.routes.webhooks = true;
+    let server = TestServer::start_with_config(config).unwrap();
+    let spec: serde_json::Value = server
+        .client()
+        .get(server.url("/openapi.json"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let documented: Vec<(String, Vec<String>)> = spec["paths"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .map(|(path, item)| {
+            let mut methods: Vec<_> = item
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(|x| x.to_uppercase())
+                .collect();
+            methods.sort();
+            (path.clone(), methods)
+        })
+        .collect();
+    let expected: Vec<(String, Vec<String>)> = DOCUMENTED_ROUTES
+        .iter()
+        .map(|(path, methods)| {
+            let methods = methods.iter().map(|x| x.to_string()).collect();
+            (path.to_string(), methods)
+        })
+        .collect();
+    assert_eq!(documented, expected);
+
+    // The methods the server accepts are checked so the list cannot drift from the routes
+    for (path, methods) in DOCUMENTED_ROUTES {
+        let url_path: String = path
+            .split('/')
+            .map(|x| if x.starts_with('{') { "1" } else { x })
+            .collect::<Vec<_>>()
+            .join("/");
+        let resp = server
+            .client()
+            .request(reqwest::Method::OPTIONS, server.url(&url_path))
+            .send()
+            .await
+            .unwrap();
+        let mut allowed: Vec<_> = resp.headers()[header::ALLOW]
+            .to_str()
+            .unwrap()
+            .split(", ")
+            .filter(|x| !["HEAD", "OPTIONS"].contains(x))
+            .collect();
+        allowed.sort();
+        assert_eq!(allowed, *methods, "{path}");
+    }
+}
+
+#[tokio::test]
+async fn metrics_record_requests_by_route() {
+    let server = TestServer::start().unwrap();
+    let counter = r#"http_requests_total{method="GET",route="/bytes/{n}",status="400"}"#;
+    let latency = r#"http_request_duration_seconds_count{method="GET",route="/bytes/{n}"}"#;
+    let before = (
+        metric_value(&server, counter).await,
+        metric_value(&server, latency).await,
+    );
+    let resp = server
+        .client()
+        .get(server.url("/bytes/999999999"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(metric_value(&server, counter).await > before.0);
+    assert!(metric_value(&server, latency).await > before.1);
+
+    let method = reqwest::Method::from_bytes(b"FOO1").unwrap();
+    server
+        .client()
+        .request(method, server.url("/anything"))
+        .send()
+        .await
+        .unwrap();
+    let body = server
+        .client()
+        .get(server.url("/metrics"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains(r#"method="OTHER""#), "{body}");
+    assert!(!body.contains("FOO1"), "{body}");
+}
+
+/// Writes `contents` to a config file unique to the test and loads it
+fn config_file(name: &str, contents: &str) -> (std::path::PathBuf, ServerConfig) {
+    let dir = std::env::temp_dir().join(format!("http_test_{name}_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("http_test.toml");
+    std::fs::write(&path, contents).unwrap();
+    let config = ServerConfig::load_from(&path).unwrap();
+    (path, config)
+}
+
+/// Sets the number of workers to match what [`TestServer`] uses so it is not reported as changed
+///
+/// Ends in the `routes` section so tests can add to it.
+const ADMIN_RELOAD_CONFIG: &str = "
+[server]
+workers = 1
+
+[reload]
+watch = false
+
+[routes]
+admin = true
+";
+
+#[tokio::test]
+async fn admin_reload_swaps_routes_and_cors_keeping_state() {
+    let (path, config) = config_file("admin_reload", ADMIN_RELOAD_CONFIG);
+    let server = TestServer::start_with_config(config).unwrap();
+    let cors_origin = |resp: &reqwest::Response| {
+        resp.headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|x| x.to_str().unwrap().to_string())
+    };
+    let resp = server
+        .client()
+        .get(server.url("/ratelimit/reload?capacity=2"))
+        .header(header::ORIGIN, "http://example.com")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(cors_origin(&resp).as_deref(), Some("http://example.com"));
+
+    std::fs::write(
+        &path,
+        format!(
+            r#"{ADMIN_RELOAD_CONFIG}
+        echo = false
+
+        [cors]
+        permissive = false
+        allowed_origins = ["http://allowed.example.com"]
+
+        [limits]
+        json = 10
+        "#
+        ),
+    )
+    .unwrap();
+    let resp = server
+        .client()
+        .post(server.url("/_admin/reload"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let outcome: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(outcome["generation"], 1);
+    assert_eq!(outcome["changed"], true);
+    assert_eq!(outcome["restart_required"], serde_json::json!(["limits"]));
+
+    let resp = server
+        .client()
+        .get(server.url("/echo"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = server
+        .client()
+        .get(server.url("/ratelimit/reload/status"))
+        .header(header::ORIGIN, "http://allowed.example.com")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        resp.status(),
+        StatusCode::OK,
+        "bucket should survive reload"
+    );
+    assert_eq!(
+        cors_origin(&resp).as_deref(),
+        Some("http://allowed.example.com")
+    );
+    let resp = server
+        .client()
+        .get(server.url("/healthz"))
+        .header(header::ORIGIN, "http://example.com")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(cors_origin(&resp), None);
+
+    // An invalid config is rejected and the current one kept
+    std::fs::write(&path, "[routes]\nunknown = true\n").unwrap();
+    let resp = server
+        .client()
+        .post(server.url("/_admin/reload"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let resp = server
+        .client()
+        .get(server.url("/echo"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn admin_reload_without_config_file_fails() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .post(server.url("/_admin/reload"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND, "off by default");
+
+    let mut config = ServerConfig::default();
+    config.routes.admin = true;
+    let server = TestServer::start_with_config(config).unwrap();
+    let resp = server
+        .client()
+        .post(server.url("/_admin/reload"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn changed_config_file_is_reloaded() {
+    let (path, config) = config_file("watch", "");
+    let server = TestServer::start_with_config(config).unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/echo"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    std::fs::write(&path, "[routes]\necho = false\n").unwrap();
+    let mut status = StatusCode::OK;
+    for _ in 0..50 {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        status = server
+            .client()
+            .get(server.url("/echo"))
+            .send()
+            .await
+            .unwrap()
+            .status();
+        if status == StatusCode::NOT_FOUND {
+            break;
+        }
+    }
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn request_id_is_generated() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/healthz"))
+        .send()
+        .await
+        .unwrap();
+    let request_id = resp
+        .headers()
+        .get("x-request-id")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert!(!request_id.is_empty());
+}
+
+#[tokio::test]
+async fn request_id_is_propagated() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .post(server.url("/echo"))
+        .header("x-request-id", "client-test-42")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()["x-request-id"], "client-test-42");
+    let body = resp.text().await.unwrap();
+    assert!(
+        body.contains("-- request id --\nclient-test-42\n"),
+        "{body}"
+    );
+
+    let resp = server
+        .client()
+        .get(server.url("/does_not_exist"))
+        .header("x-request-id", "client-test-43")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()["x-request-id"], "client-test-43");
+    let problem: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(problem["request_id"], "client-test-43");
+
+    let resp = server
+        .client()
+        .get(server.url("/ratelimit/request_id?capacity=0"))
+        .header("x-request-id", "client-test-44")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let problem: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(problem["request_id"], "client-test-44");
+}
+
+#[tokio::test]
+async fn invalid_request_id_is_replaced() {
+    let server = TestServer::start().unwrap();
+    let too_long = "a".repeat(129);
+    let resp = server
+        .client()
+        .get(server.url("/healthz"))
+        .header("x-request-id", &too_long)
+        .send()
+        .await
+        .unwrap();
+    let request_id = resp.headers()["x-request-id"].to_str().unwrap();
+    assert_ne!(request_id, too_long);
+    assert!(!request_id.is_empty());
+}
+
+#[tokio::test]
+async fn response_returns_described_response() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/response"))
+        .query(&[
+            ("status", "418"),
+            ("header", "X-Repeated: one"),
+            ("header", "X-Repeated: two"),
+            ("content_type", "text/csv"),
+            ("body", "YSxiCjEsMgo="),
+            ("body_encoding", "base64"),
+            ("set_cookie", "first=1; Path=/"),
+            ("set_cookie", "second=2"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::IM_A_TEAPOT);
+    let repeated: Vec<_> = resp.headers().get_all("x-repeated").iter().collect();
+    assert_eq!(repeated, ["one", "two"]);
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/csv");
+    assert_eq!(resp.headers().get_all(header::SET_COOKIE).iter().count(), 2);
+    assert_eq!(resp.text().await.unwrap(), "a,b\n1,2\n");
+}
+
+#[tokio::test]
+async fn response_accepts_json_description() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .post(server.url("/response"))
+        .json(&serde_json::json!({
+            "status": 503,
+            "headers": ["Retry-After: 5"],
+            "body": "try later",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(resp.headers()[header::RETRY_AFTER], "5");
+    assert_eq!(resp.text().await.unwrap(), "try later");
+
+    let resp = server
+        .client()
+        .get(server.url("/response?status=1000"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    for status in ["100", "101"] {
+        let resp = server
+            .client()
+            .get(server.url(&format!("/response?status={status}")))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            resp.headers()[header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+    }
+}
+
+#[tokio::test]
+async fn anything_echoes_request() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .put(server.url("/anything/some/path?status=201&a=1&a=2&header=X-Test:%20yes"))
+        .json(&serde_json::json!({"key": "value"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert_eq!(resp.headers()["x-test"], "yes");
+    let echo: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(echo["method"], "PUT");
+    assert!(echo["url"]
+        .as_str()
+        .unwrap()
+        .starts_with("/anything/some/path?"));
+    assert_eq!(echo["args"]["a"], serde_json::json!(["1", "2"]));
+    assert_eq!(echo["json"]["key"], "value");
+    assert_eq!(echo["headers"]["content-type"][0], "application/json");
+}
+
+#[tokio::test]
+async fn response_headers_are_set_from_query() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/response-headers?X-One=1&X-One=2"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get_all("x-one").iter().count(), 2);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["X-One"], serde_json::json!(["1", "2"]));
+}
+
+#[tokio::test]
+async fn samples_have_listed_content_types() {
+    let server = TestServer::start().unwrap();
+    let list: Vec<serde_json::Value> = server
+        .client()
+        .get(server.url("/samples"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(list.len() >= 14);
+    for info in list {
+        let kind = info["kind"].as_str().unwrap();
+        let resp = server
+            .client()
+            .get(server.url(&format!("/samples/{kind}")))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK, "{kind}");
+        let content_type = resp
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map(|x| x.to_str().unwrap().to_string());
+        assert_eq!(
+            content_type.as_deref(),
+            info["content_type"].as_str(),
+            "{kind}"
+        );
+        let actual = resp.headers().get("x-actual-content-type").is_some();
+        assert_eq!(actual, !info["actual_content_type"].is_null(), "{kind}");
+        let body = resp.bytes().await.unwrap();
+        assert_eq!(
+            body.len() as u64,
+            info["length"].as_u64().unwrap(),
+            "{kind}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn samples_are_deterministic() {
+    let server = TestServer::start().unwrap();
+    let get = || async {
+        server
+            .client()
+            .get(server.url("/samples/png"))
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap()
+    };
+    let first = get().await;
+    assert!(first.starts_with(b"\x89PNG\r\n\x1a\n"));
+    assert_eq!(first, get().await);
+
+    let resp = server
+        .client()
+        .get(server.url("/samples/unknown"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn image_samples_decode() {
+    let server = TestServer::start().unwrap();
+    for (kind, format, size) in [
+        ("png", image::ImageFormat::Png, (16, 16)),
+        ("jpeg", image::ImageFormat::Jpeg, (8, 8)),
+        ("webp", image::ImageFormat::WebP, (1, 1)),
+        ("gif", image::ImageFormat::Gif, (1, 1)),
+    ] {
+        let body = server
+            .client()
+            .get(server.url(&format!("/samples/{kind}")))
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let decoded = image::load_from_memory_with_format(&body, format)
+            .unwrap_or_else(|err| panic!("{kind}: {err}"));
+        assert_eq!((decoded.width(), decoded.height()), size, "{kind}");
+    }
+}
+
+#[tokio::test]
+async fn encodings_decode_to_expected_text() {
+    let server = TestServer::start().unwrap();
+    let list: Vec<serde_json::Value> = server
+        .client()
+        .get(server.url("/encoding"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(list.len() >= 8);
+    for info in list {
+        let name = info["name"].as_str().unwrap();
+        let resp = server
+            .client()
+            .get(server.url(&format!("/encoding/{name}")))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK, "{name}");
+        assert_eq!(
+            resp.headers()[header::CONTENT_TYPE],
+            info["content_type"].as_str().unwrap()
+        );
+        let body = resp.bytes().await.unwrap();
+        let encoding = match info["encoding"].as_str().unwrap() {
+            // The WHATWG label maps to Windows-1252 which decodes the text the same way
+            "ISO-8859-1" => encoding_rs::WINDOWS_1252,
+            label => encoding_rs::Encoding::for_label(label.as_bytes()).unwrap(),
+        };
+        let (text, had_errors) = encoding.decode_with_bom_removal(&body);
+        assert!(!had_errors, "{name}");
+        assert_eq!(text, info["expected_text"].as_str().unwrap(), "{name}");
+    }
+
+    let body = server
+        .client()
+        .get(server.url("/encoding/utf-8"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("こんにちは世界") && body.contains("🚀"));
+}
+
+#[tokio::test]
+async fn stream_sends_one_object_per_line() {
+    let server = TestServer::start().unwrap();
+    let body = server
+        .client()
+        .get(server.url("/stream/3"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let ids: Vec<u64> = body
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<serde_json::Value>(line).unwrap()["id"]
+                .as_u64()
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(ids, [0, 1, 2]);
+}
+
+/// reqwest drops trailers so the response is read from a plain TCP connection
+#[tokio::test]
+async fn stream_checksum_sends_trailer() {
+    use base64::Engine as _;
+    use sha2::Digest as _;
+    use std::io::{Read as _, Write as _};
+
+    let server = TestServer::start().unwrap();
+    let addr = server.addr();
+    // Blocking IO runs off the test runtime which also drives the server
+    let resp = tokio::task::spawn_blocking(move || {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"GET /stream/5/checksum HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        resp
+    })
+    .await
+    .unwrap();
+    let (head, mut rest) = resp.split_once("\r\n\r\n").unwrap();
+    let head = head.to_lowercase();
+    assert!(head.contains("\r\ntransfer-encoding: chunked"), "{head}");
+    assert!(head.contains("\r\ntrailer: content-digest"), "{head}");
+    assert!(!head.contains("content-length"), "{head}");
+
+    let mut body = String::new();
+    loop {
+        let (size, after) = rest.split_once("\r\n").unwrap();
+        let size = usize::from_str_radix(size, 16).unwrap();
+        if size == 0 {
+            rest = after;
+            break;
+        }
+        body.push_str(&after[..size]);
+        rest = after[size..].strip_prefix("\r\n").unwrap();
+    }
+    assert_eq!(body.lines().count(), 5);
+    let expected = base64::engine::general_purpose::STANDARD.encode(sha2::Sha256::digest(&body));
+    assert_eq!(
+        rest,
+        format!("Content-Digest: sha-256=:{expected}:\r\n\r\n")
+    );
+
+    let client = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let resp = client
+        .get(server.url("/stream/5/checksum"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn ndjson_chunks_arrive_with_delay() {
+    let server = TestServer::start().unwrap();
+    let start = std::time::Instant::now();
+    let mut resp = server
+        .client()
+        .get(server.url("/ndjson/3?delay_ms=100"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "application/x-ndjson");
+    let mut chunks = vec![];
+    while let Some(chunk) = resp.chunk().await.unwrap() {
+        chunks.push((start.elapsed(), chunk));
+    }
+    assert_eq!(chunks.len(), 3);
+    assert!(chunks[2].0 - chunks[0].0 >= std::time::Duration::from_millis(200));
+    for (_, chunk) in chunks {
+        assert!(chunk.ends_with(b"\n"));
+    }
+}
+
+async fn protocol(client: &reqwest::Client, url: &str) -> serde_json::Value {
+    client.get(url).send().await.unwrap().json().await.unwrap()
+}
+
+#[tokio::test]
+async fn protocol_reports_reused_http1_connection() {
+    let server = TestServer::start().unwrap();
+    let url = server.url("/protocol");
+    let first = protocol(server.client(), &url).await;
+    let second = protocol(server.client(), &url).await;
+    assert_eq!(first["version"], "HTTP/1.1");
+    assert_eq!(first["tls"], false);
+    assert_eq!(first["alpn"], serde_json::Value::Null);
+    assert_eq!(first["reused"], false);
+    assert_eq!(second["reused"], true);
+    assert_eq!(second["request_number"], 2);
+    assert_eq!(first["connection_id"], second["connection_id"]);
+}
+
+#[tokio::test]
+async fn protocol_reports_multiplexed_h2c() {
+    let server = TestServer::start().unwrap();
+    let client = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let url = server.url("/protocol");
+    let (first, second) = tokio::join!(protocol(&client, &url), protocol(&client, &url));
+    assert_eq!(first["version"], "HTTP/2.0");
+    assert_eq!(first["connection_id"], second["connection_id"]);
+    let mut numbers = [&first, &second].map(|x| x["request_number"].as_u64().unwrap());
+    numbers.sort();
+    assert_eq!(numbers, [1, 2]);
+}
+
+#[tokio::test]
+async fn h2c_can_be_turned_off() {
+    let mut config = ServerConfig::default();
+    config.server.h2c = false;
+    let server = TestServer::start_with_config(config).unwrap();
+    let client = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    assert!(client.get(server.url("/protocol")).send().await.is_err());
+}
+
+#[tokio::test]
+async fn protocol_reports_alpn_over_tls() {
+    let dir = std::env::temp_dir().join(format!("http_test_tls_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let cert = rcgen::generate_simple_self_signed(["localhost".to_string()]).unwrap();
+    std::fs::write(dir.join("cert.pem"), cert.cert.pem()).unwrap();
+    std::fs::write(dir.join("key.pem"), cert.key_pair.serialize_pem()).unwrap();
+    // TLS addresses are bound by the server so find a free port first
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut config = ServerConfig::default();
+    config.tls = Some(http_test_server::config::TlsConfig {
+        listen: vec![addr],
+        cert_file: dir.join("cert.pem"),
+        key_file: dir.join("key.pem"),
+    });
+    let _server = TestServer::start_with_config(config).unwrap();
+    let url = format!("https://localhost:{}/protocol", addr.port());
+
+    let client = reqwest::Client::builder()
+        .use_rustls_tls()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let body = protocol(&client, &url).await;
+    assert_eq!(body["version"], "HTTP/2.0");
+    assert_eq!(body["tls"], true);
+    assert_eq!(body["alpn"], "h2");
+
+    let client = reqwest::Client::builder()
+        .use_rustls_tls()
+        .danger_accept_invalid_certs(true)
+        .http1_only()
+        .build()
+        .unwrap();
+    let body = protocol(&client, &url).await;
+    assert_eq!(body["version"], "HTTP/1.1");
+    assert_eq!(body["alpn"], "http/1.1");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+fn reverse_proxy_config(upstream: String) -> ServerConfig {
+    ServerConfig::from_toml(&format!(
+        r#"
+        [proxy.reverse]
+        prefix = "/app"
+        upstream = "{upstream}"
+
+        [[proxy.rewrite]]
+        direction = "request"
+        add_headers = ["X-Proxied: yes"]
+        strip_cookies = ["tracking"]
+
+        [[proxy.rewrite]]
+        direction = "response"
+        strip_cookies = ["tracking"]
+        "#
+    ))
+    .unwrap()
+}
+
+async fn proxy_captures(server: &TestServer) -> Vec<serde_json::Value> {
+    server
+        .client()
+        .get(server.url("/_proxy/captures"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn reverse_proxy_rewrites_and_captures() {
+    let upstream = TestServer::start().unwrap();
+    let server = TestServer::start_with_config(reverse_proxy_config(upstream.base_url())).unwrap();
+    let resp =
+        server
+            .client()
+            .get(server.url(
+                "/app/anything?set_cookie=session%3D1&set_cookie=tracking%3D2%3B%20Path%3D%2F",
+            ))
+            .header(header::COOKIE, "a=1; tracking=x")
+            .send()
+            .await
+            .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let set_cookies: Vec<_> = resp.headers().get_all(header::SET_COOKIE).iter().collect();
+    assert_eq!(set_cookies, ["session=1"]);
+    let echo: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(
+        echo["url"],
+        "/anything?set_cookie=session%3D1&set_cookie=tracking%3D2%3B%20Path%3D%2F"
+    );
+    assert_eq!(echo["headers"]["cookie"], serde_json::json!(["a=1"]));
+    assert_eq!(echo["headers"]["x-proxied"], serde_json::json!(["yes"]));
+    assert_eq!(
+        echo["headers"]["x-forwarded-host"],
+        serde_json::json!([server.addr().to_string()])
+    );
+
+    let captures = proxy_captures(&server).await;
+    assert_eq!(captures.len(), 1);
+    let capture = &captures[0];
+    assert_eq!(capture["mode"], "reverse");
+    assert!(capture["request"]["url"]
+        .as_str()
+        .unwrap()
+        .starts_with(&upstream.url("/anything")));
+    // Captures show the traffic before the rewrite rules
+    assert_eq!(
+        capture["request"]["headers"]["cookie"],
+        serde_json::json!(["a=1; tracking=x"])
+    );
+    assert_eq!(capture["response"]["status"], 200);
+    assert_eq!(
+        capture["response"]["headers"]["set-cookie"],
+        serde_json::json!(["session=1", "tracking=2; Path=/"])
+    );
+
+    let resp = server
+        .client()
+        .delete(server.url("/_proxy/captures"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert!(proxy_captures(&server).await.is_empty());
+}
+
+#[tokio::test]
+async fn proxy_drops_headers_named_in_connection() {
+    // actix-web does not send `Connection` headers set by handlers so the upstream is raw
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let upstream = format!("http://{}", listener.local_addr().unwrap());
+    let upstream_thread = std::thread::spawn(move || {
+        use std::io::{BufRead as _, Write as _};
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = vec![];
+        let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            request.push(line.trim_end().to_lowercase());
+        }
+        stream
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nConnection: X-Upstream-Hop\r\nX-Upstream-Hop: 1\r\n\
+                X-Upstream-Kept: 1\r\nContent-Length: 2\r\n\r\nok",
+            )
+            .unwrap();
+        request
+    });
+    let server = TestServer::start_with_config(reverse_proxy_config(upstream)).unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/app/"))
+        .header(header::CONNECTION, "x-client-hop")
+        .header("x-client-hop", "1")
+        .header("x-client-kept", "1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(!resp.headers().contains_key("x-upstream-hop"));
+    assert_eq!(resp.headers()["x-upstream-kept"], "1");
+    let request = upstream_thread.join().unwrap();
+    assert!(
+        request.contains(&"x-client-kept: 1".to_string()),
+        "{request:?}"
+    );
+    assert!(
+        !request.iter().any(|x| x.starts_with("x-client-hop")),
+        "{request:?}"
+    );
+}
+
+#[tokio::test]
+async fn forward_proxy_captures_absolute_urls() {
+    let upstream = TestServer::start().unwrap();
+    let mut config = ServerConfig::default();
+    config.proxy.forward = true;
+    let server = TestServer::start_with_config(config).unwrap();
+    let client = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::http(server.base_url()).unwrap())
+        .build()
+        .unwrap();
+    let resp = client
+        .get(upstream.url("/response?status=418&body=teapot"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::IM_A_TEAPOT);
+    assert!(resp.headers().contains_key("x-request-id"));
+    assert_eq!(resp.text().await.unwrap(), "teapot");
+
+    let captures = proxy_captures(&server).await;
+    assert_eq!(captures.len(), 1);
+    assert_eq!(captures[0]["mode"], "forward");
+    assert_eq!(captures[0]["response"]["body"]["content"], "teapot");
+}
+
+#[tokio::test]
+async fn proxy_reports_unreachable_upstream() {
+    // Nothing listens on the port once the listener is dropped
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let server =
+        TestServer::start_with_config(reverse_proxy_config(format!("http://{addr}"))).unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/app/"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+    let captures = proxy_captures(&server).await;
+    assert!(captures[0]["response"].is_null());
+    assert!(captures[0]["error"]
+        .as_str()
+        .unwrap()
+        .contains(&addr.to_string()));
+}
+
+#[tokio::test]
+async fn shaping_header_adds_latency() {
+    let server = TestServer::start().unwrap();
+    let start = std::time::Instant::now();
+    let resp = server
+        .client()
+        .get(server.url("/cookies/"))
+        .header("x-test-shape", "latency_ms=200, jitter_ms=0")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(start.elapsed() >= std::time::Duration::from_millis(200));
+    assert_eq!(resp.headers()["x-test-shape-applied"], "latency_ms=200");
+}
+
+#[tokio::test]
+async fn shaping_query_limits_bandwidth() {
+    let server = TestServer::start().unwrap();
+    let start = std::time::Instant::now();
+    let resp = server
+        .client()
+        .get(server.url("/range/1000?_shape=bytes_per_sec%3D4000"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()[header::CONTENT_LENGTH], "1000");
+    assert_eq!(resp.bytes().await.unwrap().len(), 1000);
+    assert!(start.elapsed() >= std::time::Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn shaping_failures_and_invalid_shapes() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/healthz"))
+        .header("x-test-shape", "failure_rate=1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(resp.headers().contains_key("x-request-id"));
+    let problem: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(problem["type"], "urn:http-test:problem:simulated-failure");
+
+    let resp = server
+        .client()
+        .get(server.url("/healthz"))
+        .header("x-test-shape", "latency=5")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn shaping_rules_from_config() {
+    let config = ServerConfig::from_toml(
+        r#"
+        [shaping]
+        from_requests = false
+
+        [[shaping.rules]]
+        prefix = "/healthz"
+        shape = "failure_rate=1"
+        "#,
+    )
+    .unwrap();
+    let server = TestServer::start_with_config(config).unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/healthz"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    // Requests cannot choose their own conditions when turned off
+    let resp = server
+        .client()
+        .get(server.url("/version"))
+        .header("x-test-shape", "failure_rate=1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+async fn cookie_stress(server: &TestServer, path: &str) -> (Vec<String>, serde_json::Value) {
+    let resp = server.client().get(server.url(path)).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK, "{path}");
+    let set_cookies = resp
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|x| String::from_utf8_lossy(x.as_bytes()).into_owned())
+        .collect();
+    (set_cookies, resp.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn cookie_stress_sets_many() {
+    let server = TestServer::start().unwrap();
+    let (set_cookies, body) = cookie_stress(&server, "/cookies/stress/many?count=60").await;
+    assert_eq!(set_cookies.len(), 60);
+    assert_eq!(body.as_array().unwrap().len(), 60);
+    assert_eq!(cookies(&server).await.len(), 60);
+}
+
+#[tokio::test]
+async fn cookie_stress_headers_match_listing() {
+    let server = TestServer::start().unwrap();
+    for variant in ["large", "duplicates", "quoted", "expiry"] {
+        let (set_cookies, body) =
+            cookie_stress(&server, &format!("/cookies/stress/{variant}")).await;
+        let listed: Vec<_> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["set_cookie"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(set_cookies, listed, "{variant}");
+    }
+}
+
+#[tokio::test]
+async fn cookie_stress_expiry_max_age_wins() {
+    let server = TestServer::start().unwrap();
+    cookie_stress(&server, "/cookies/stress/expiry").await;
+    let names: Vec<_> = cookies(&server)
+        .await
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    assert!(names.contains(&"max_age_wins".to_string()), "{names:?}");
+    for removed in ["max_age_zero", "max_age_negative", "expires_past"] {
+        assert!(!names.contains(&removed.to_string()), "{names:?}");
+    }
+}
+
+#[tokio::test]
+async fn cookie_stress_rejects_unknown_and_too_many() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/cookies/stress/unknown"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = server
+        .client()
+        .get(server.url("/cookies/stress/many?count=1001"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn scoped_cookies_match_expected() {
+    let server = TestServer::start().unwrap();
+    let expected: serde_json::Value = server
+        .client()
+        .get(server.url("/scoped/set"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let pages = expected["pages"].as_array().unwrap();
+    assert_eq!(pages.len(), 4);
+    for page in pages {
+        let path = page["path"].as_str().unwrap();
+        let mut received: Vec<(String, String)> = server
+            .client()
+            .get(server.url(path))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let mut expected: Vec<(String, String)> =
+            serde_json::from_value(page["cookies"].clone()).unwrap();
+        // The order is only recommended and reqwest does not follow it
+        received.sort();
+        expected.sort();
+        assert_eq!(received, expected, "{path}");
+    }
+}
+
+#[tokio::test]
+async fn scoped_cookies_expected_paths() {
+    let server = TestServer::start().unwrap();
+    let expected: serde_json::Value = server
+        .client()
+        .get(server.url("/scoped/expected"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let names = |path: &str| -> Vec<String> {
+        let page = expected["pages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|x| x["path"] == path)
+            .unwrap();
+        page["cookies"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x[0].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(
+        names("/scoped/a/b"),
+        [
+            "path_a_b",
+            "path_a_slash",
+            "path_a",
+            "path_scoped",
+            "path_default",
+            "path_relative",
+            "domain_host"
+        ]
+    );
+    assert_eq!(
+        names("/scoped/ab"),
+        [
+            "path_ab",
+            "path_scoped",
+            "path_default",
+            "path_relative",
+            "domain_host"
+        ]
+    );
+    // No cookies were set by asking for the expectations
+    assert!(server
+        .client()
+        .get(server.url("/scoped"))
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<(String, String)>>()
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+async fn graphql_post(
+    server: &TestServer,
+    path: &str,
+    body: serde_json::Value,
+) -> serde_json::Value {
+    let resp = server
+        .client()
+        .post(server.url(path))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    resp.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn graphql_query_with_variables() {
+    let server = TestServer::start().unwrap();
+    let body = graphql_post(
+        &server,
+        "/graphql",
+        serde_json::json!({
+            "query": "query UserPosts($id: ID!) { user(id: $id) { name posts { title } } }",
+            "variables": {"id": "2"},
+            "operationName": "UserPosts",
+        }),
+    )
+    .await;
+    assert!(body.get("errors").is_none(), "{body}");
+    assert_eq!(body["data"]["user"]["name"], "Alan Turing");
+    assert_eq!(body["data"]["user"]["posts"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn graphql_partial_results() {
+    let server = TestServer::start().unwrap();
+    let body = graphql_post(
+        &server,
+        "/graphql",
+        serde_json::json!({"query": "{ users { name secret } missing: user(id: 99) { name } }"}),
+    )
+    .await;
+    assert_eq!(body["data"]["users"][0]["name"], "Ada Lovelace");
+    assert!(body["data"]["users"][0]["secret"].is_null());
+    assert!(body["data"]["missing"].is_null());
+    assert_eq!(body["errors"].as_array().unwrap().len(), 4, "{body}");
+}
+
+#[tokio::test]
+async fn graphql_get_and_introspection() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/graphql"))
+        .query(&[("query", "{ __schema { queryType { name } } }")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["data"]["__schema"]["queryType"]["name"], "QueryRoot");
+
+    let resp = server
+        .client()
+        .get(server.url("/graphql"))
+        .query(&[(
+            "query",
+            r#"mutation { createPost(authorId: "1", title: "t", body: "b") { id } }"#,
+        )])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(resp.headers()[header::ALLOW], "POST");
+}
+
+#[tokio::test]
+async fn graphql_limits_nested_queries() {
+    let server = TestServer::start().unwrap();
+    let query = format!(
+        "{{ users {}name{} }}",
+        "{ posts { author ".repeat(10),
+        " } }".repeat(10)
+    );
+    let body = graphql_post(&server, "/graphql", serde_json::json!({ "query": query })).await;
+    assert!(body.get("data").is_none_or(|x| x.is_null()), "{body}");
+    assert!(
+        body["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("too deep"),
+        "{body}"
+    );
+
+    // As nested as the type references in the introspection query GraphiQL sends
+    let query = format!(
+        "{{ __schema {{ types {{ fields {{ args {{ type {{ {}name{} }} }} }} }} }} }}",
+        "ofType { ".repeat(7),
+        " }".repeat(7)
+    );
+    let body = graphql_post(&server, "/graphql", serde_json::json!({ "query": query })).await;
+    assert!(body.get("errors").is_none(), "{body}");
+
+    let batch = vec![serde_json::json!({"query": "{ users { id } }"}); 17];
+    let resp = server
+        .client()
+        .post(server.url("/graphql"))
+        .json(&batch)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn graphql_echo_returns_parsed_request() {
+    let server = TestServer::start().unwrap();
+    let body = graphql_post(
+        &server,
+        "/graphql/echo",
+        serde_json::json!({
+            "query": "query A($id: ID! = 1) { first: user(id: $id) { ...Names } } query B { users { id } } fragment Names on User { name }",
+            "variables": {"id": "3"},
+            "operationName": "A",
+        }),
+    )
+    .await;
+    assert_eq!(body["operationName"], "A");
+    assert_eq!(body["variables"], serde_json::json!({"id": "3"}));
+    let operation = &body["operations"][0];
+    assert_eq!(operation["name"], "A");
+    assert_eq!(operation["operation_type"], "query");
+    assert_eq!(operation["variables"], serde_json::json!(["$id: ID! = 1"]));
+    assert_eq!(
+        operation["selections"][0],
+        serde_json::json!({
+            "kind": "field",
+            "name": "user",
+            "alias": "first",
+            "arguments": [["id", "$id"]],
+            "selections": [{"kind": "fragment_spread", "name": "Names"}],
+        })
+    );
+    assert_eq!(body["fragments"][0]["type_condition"], "User");
+}
+
+/// gRPC-Web frame holding an `EchoRequest` with only the message set
+fn grpc_request(message: &str) -> Vec<u8> {
+    let mut payload = vec![0x0a, message.len() as u8];
+    payload.extend_from_slice(message.as_bytes());
+    let mut result = vec![0];
+    result.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    result.extend(payload);
+    result
+}
+
+/// Splits a gRPC-Web response into its frames as `(flags, payload)`
+fn grpc_frames(mut body: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let mut result = vec![];
+    while !body.is_empty() {
+        let len = u32::from_be_bytes(body[1..5].try_into().unwrap()) as usize;
+        result.push((body[0], body[5..5 + len].to_vec()));
+        body = &body[5 + len..];
+    }
+    result
+}
+
+async fn grpc_call(
+    server: &TestServer,
+    method: &str,
+    body: Vec<u8>,
+    headers: &[(&str, &str)],
+) -> (reqwest::header::HeaderMap, Vec<(u8, Vec<u8>)>) {
+    let mut builder = server
+        .client()
+        .post(server.url(&format!("/http_test.echo.Echo/{method}")))
+        .header(header::CONTENT_TYPE, "application/grpc-web+proto")
+        .body(body);
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    let resp = builder.send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let headers = resp.headers().clone();
+    (headers, grpc_frames(&resp.bytes().await.unwrap()))
+}
+
+#[tokio::test]
+async fn grpc_web_unary_and_streams() {
+    let server = TestServer::start().unwrap();
+    let (_, frames) = grpc_call(&server, "Unary", grpc_request("hi"), &[]).await;
+    assert_eq!(
+        frames,
+        [
+            (0, b"\x0a\x02hi".to_vec()),
+            (0x80, b"grpc-status: 0\r\n".to_vec())
+        ]
+    );
+
+    let (_, frames) = grpc_call(&server, "ServerStream", grpc_request("hi"), &[]).await;
+    assert_eq!(frames.len(), 4);
+    assert_eq!(frames[2], (0, b"\x0a\x02hi\x10\x02".to_vec()));
+
+    let mut body = grpc_request("a");
+    body.extend(grpc_request("b"));
+    let (_, frames) = grpc_call(&server, "ClientStream", body.clone(), &[]).await;
+    assert_eq!(frames[0], (0, b"\x0a\x03a b\x10\x02".to_vec()));
+    let (_, frames) = grpc_call(&server, "Bidi", body, &[]).await;
+    assert_eq!(frames[1], (0, b"\x0a\x01b\x10\x01".to_vec()));
+}
+
+#[tokio::test]
+async fn grpc_web_controlled_status_and_trailers() {
+    let server = TestServer::start().unwrap();
+    let (_, frames) = grpc_call(
+        &server,
+        "Unary",
+        grpc_request("hi"),
+        &[
+            ("x-test-grpc-status", "5"),
+            ("x-test-grpc-message", "not found 100%"),
+            ("x-test-grpc-trailer-extra", "value"),
+        ],
+    )
+    .await;
+    assert_eq!(
+        frames,
+        [(
+            0x80,
+            b"grpc-status: 5\r\ngrpc-message: not found 100%25\r\nextra: value\r\n".to_vec()
+        )]
+    );
+
+    let (headers, frames) = grpc_call(&server, "Missing", grpc_request("hi"), &[]).await;
+    assert_eq!(headers["grpc-status"], "12");
+    assert!(frames.is_empty());
+}
+
+#[tokio::test]
+async fn grpc_web_text_and_native() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .post(server.url("/http_test.echo.Echo/Unary"))
+        .header(header::CONTENT_TYPE, "application/grpc-web-text")
+        .body("AAAAAAQKAmhp")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        resp.headers()[header::CONTENT_TYPE],
+        "application/grpc-web-text+proto"
+    );
+    assert_eq!(
+        resp.text().await.unwrap(),
+        "AAAAAAQKAmhpgAAAABBncnBjLXN0YXR1czogMA0K"
+    );
+
+    let resp = server
+        .client()
+        .post(server.url("/http_test.echo.Echo/Unary"))
+        .header(header::CONTENT_TYPE, "application/grpc")
+        .body(grpc_request("hi"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()["grpc-status"], "12");
+}
+
+/// Makes a native gRPC call returning the response headers, frames and trailers
+async fn native_grpc_call(
+    addr: std::net::SocketAddr,
+    method: &str,
+    body: Vec<u8>,
+    headers: &[(&str, &str)],
+) -> (
+    header::HeaderMap,
+    Vec<(u8, Vec<u8>)>,
+    Option<header::HeaderMap>,
+) {
+    let socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let (client, connection) = h2::client::handshake(socket).await.unwrap();
+    tokio::spawn(connection);
+    let mut builder = http::Request::post(format!("http://{addr}/http_test.echo.Echo/{method}"))
+        .header(header::CONTENT_TYPE, "application/grpc")
+        .header("te", "trailers");
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    let mut client = client.ready().await.unwrap();
+    let (resp, mut stream) = client
+        .send_request(builder.body(()).unwrap(), false)
+        .unwrap();
+    stream.send_data(body.into(), true).unwrap();
+    let (parts, mut body) = resp.await.unwrap().into_parts();
+    assert_eq!(parts.status, StatusCode::OK);
+    let mut data = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.unwrap();
+        body.flow_control().release_capacity(chunk.len()).unwrap();
+        data.extend_from_slice(&chunk);
+    }
+    let trailers = body.trailers().await.unwrap();
+    (parts.headers, grpc_frames(&data), trailers)
+}
+
+#[tokio::test]
+async fn native_grpc_sends_trailers() {
+    let mut config = ServerConfig::default();
+    config.grpc.listen = vec!["127.0.0.1:0".parse().unwrap()];
+    let server = TestServer::start_with_config(config).unwrap();
+    let addr = server.grpc_addrs()[0];
+
+    let (headers, frames, trailers) = native_grpc_call(
+        addr,
+        "ServerStream",
+        grpc_request("hi"),
+        &[
+            ("x-test-grpc-status", "5"),
+            ("x-test-grpc-trailer-extra", "value"),
+        ],
+    )
+    .await;
+    assert_eq!(headers[header::CONTENT_TYPE], "application/grpc+proto");
+    assert_eq!(frames.len(), 3);
+    assert_eq!(frames[0], (0, b"\x0a\x02hi".to_vec()));
+    let trailers = trailers.unwrap();
+    assert_eq!(trailers["grpc-status"], "5");
+    assert_eq!(trailers["extra"], "value");
+
+    let mut body = grpc_request("a");
+    body.extend(grpc_request("b"));
+    let (_, frames, trailers) = native_grpc_call(addr, "ClientStream", body, &[]).await;
+    assert_eq!(frames, [(0, b"\x0a\x03a b\x10\x02".to_vec())]);
+    assert_eq!(trailers.unwrap()["grpc-status"], "0");
+
+    let (headers, frames, trailers) =
+        native_grpc_call(addr, "Missing", grpc_request("hi"), &[]).await;
+    assert_eq!(headers["grpc-status"], "12");
+    assert!(frames.is_empty());
+    assert!(trailers.is_none());
+}
+
+/// The value of the metric line starting with `prefix` or 0 if it is missing
+async fn metric_value(server: &TestServer, prefix: &str) -> f64 {
+    let body = server
+        .client()
+        .get(server.url("/metrics"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    body.lines()
+        .find_map(|line| line.strip_prefix(prefix))
+        .map_or(0.0, |x| x.trim().parse().unwrap())
+}
+
+#[tokio::test]
+async fn native_grpc_is_recorded_and_follows_route_groups() {
+    let mut config = ServerConfig::default();
+    config.grpc.listen = vec!["127.0.0.1:0".parse().unwrap()];
+    let server = TestServer::start_with_config(config.clone()).unwrap();
+    let addr = server.grpc_addrs()[0];
+    let counter =
+        r#"http_requests_total{method="POST",route="/http_test.echo.Echo/{method}",status="200"}"#;
+    let before = metric_value(&server, counter).await;
+    let (headers, _, trailers) = native_grpc_call(
+        addr,
+        "Unary",
+        grpc_request("hi"),
+        &[("x-request-id", "native-call")],
+    )
+    .await;
+    assert_eq!(headers["x-request-id"], "native-call");
+    assert_eq!(trailers.unwrap()["grpc-status"], "0");
+    assert!(metric_value(&server, counter).await > before);
+
+    config.routes.grpc = false;
+    let server = TestServer::start_with_config(config).unwrap();
+    let (headers, frames, _) =
+        native_grpc_call(server.grpc_addrs()[0], "Unary", grpc_request("hi"), &[]).await;
+    assert_eq!(headers["grpc-status"], "12");
+    assert!(frames.is_empty());
+}
+
+#[tokio::test]
+async fn dashboard_lists_recent_requests() {
+    let server = TestServer::start().unwrap();
+    server
+        .client()
+        .get(server.url("/healthz?from=test"))
+        .send()
+        .await
+        .unwrap();
+    let resp = server
+        .client()
+        .get(server.url("/_dashboard"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let page = resp.text().await.unwrap();
+    assert!(page.contains("<td>/healthz?from=test</td>"), "{page}");
+
+    let mut resp = server
+        .client()
+        .get(server.url("/_dashboard/events"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/event-stream");
+    let event = resp.chunk().await.unwrap().unwrap();
+    let event = std::str::from_utf8(&event).unwrap();
+    let data = event.strip_prefix("event: snapshot\ndata: ").unwrap();
+    let snapshot: serde_json::Value = serde_json::from_str(data.trim_end()).unwrap();
+    let paths: Vec<_> = snapshot["requests"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, ["/healthz?from=test"]);
+
+    // The open event stream only holds up stopping until the shutdown timeout
+    let start = std::time::Instant::now();
+    server.stop().await;
+    assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    drop(resp);
+}
+
+#[tokio::test]
+async fn head_matches_get_without_body() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .head(server.url("/bytes/16"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[header::CONTENT_LENGTH], "16");
+    assert!(resp.bytes().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn options_and_unsupported_methods_list_allowed_methods() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .request(reqwest::Method::OPTIONS, server.url("/healthz"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert_eq!(resp.headers()[header::ALLOW], "GET, HEAD, OPTIONS");
+
+    let resp = server
+        .client()
+        .post(server.url("/healthz"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(resp.headers()[header::ALLOW], "GET, HEAD, OPTIONS");
+    assert_eq!(
+        resp.headers()[header::CONTENT_TYPE],
+        "application/problem+json"
+    );
+
+    let resp = server
+        .client()
+        .request(reqwest::Method::OPTIONS, server.url("/_proxy/captures"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert_eq!(resp.headers()[header::ALLOW], "GET, DELETE, HEAD, OPTIONS");
+
+    let resp = server
+        .client()
+        .request(reqwest::Method::OPTIONS, server.url("/echo/some/path"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers()[header::ALLOW],
+        "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS"
+    );
+}
+
+#[tokio::test]
+async fn trace_is_opt_in() {
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .request(reqwest::Method::TRACE, server.url("/echo"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    let mut config = ServerConfig::default();
+    config.routes.trace = true;
+    let server = TestServer::start_with_config(config).unwrap();
+    let resp = server
+        .client()
+        .request(reqwest::Method::TRACE, server.url("/healthz"))
+        .header("x-trace-me", "yes")
+        .header(header::AUTHORIZATION, "Bearer secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "message/http");
+    let body = resp.text().await.unwrap();
+    assert!(body.starts_with("TRACE /healthz HTTP/1.1\r\n"), "{body}");
+    assert!(body.contains("x-trace-me: yes\r\n"), "{body}");
+    assert!(!body.contains("secret"), "{body}");
+}
+
+/// Waits for the webhook with `id` to stop being pending
+async fn finished_delivery(server: &TestServer, id: u64) -> serde_json::Value {
+    for _ in 0..100 {
+        let deliveries: Vec<serde_json::Value> = server
+            .client()
+            .get(server.url("/_webhooks/deliveries"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let delivery = deliveries.into_iter().find(|x| x["id"] == id).unwrap();
+        if delivery["state"] != "pending" {
+            return delivery;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("webhook {id} is still pending");
+}
+
+fn webhook_server() -> TestServer {
+    let mut config = ServerConfig::default();
+    config.routes.webhooks = true;
+    TestServer::start_with_config(config).unwrap()
+}
+
+fn hmac_hex(secret: &str, message: &str) -> String {
+    use hmac::{Hmac, Mac as _};
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(message.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect()
+}
+
+#[tokio::test]
+async fn webhook_is_signed_and_delivered() {
+    let server = webhook_server();
+    let resp = server
+        .client()
+        .post(server.url("/_webhooks/send"))
+        .json(&serde_json::json!({
+            "url": server.url("/anything/hook"),
+            "event": "order.created",
+            "payload": {"order": 1},
+            "timestamp": 1_700_000_000,
+            "secret": "test-secret",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    let scheduled: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(scheduled["state"], "pending");
+
+    let delivery = finished_delivery(&server, scheduled["id"].as_u64().unwrap()).await;
+    assert_eq!(delivery["state"], "delivered");
+    assert_eq!(delivery["attempts"][0]["status"], 200);
+    let body = r#"{"order":1}"#;
+    assert_eq!(delivery["body"], body);
+    let headers = &delivery["headers"];
+    assert_eq!(headers["x-webhook-event"], "order.created");
+    assert_eq!(
+        headers["x-signature-256"],
+        format!("sha256={}", hmac_hex("test-secret", body))
+    );
+    assert_eq!(
+        headers["x-signature"],
+        format!(
+            "t=1700000000,v1={}",
+            hmac_hex("test-secret", &format!("1700000000.{body}"))
+        )
+    );
+}
+
+#[tokio::test]
+async fn webhook_is_retried_until_max_attempts() {
+    let server = webhook_server();
+    let scheduled: serde_json::Value = server
+        .client()
+        .post(server.url("/_webhooks/send"))
+        .json(&serde_json::json!({
+            // The payload is the response described to `/response`
+            "url": server.url("/response"),
+            "payload": {"status": 503},
+            "max_attempts": 3,
+            "backoff_ms": 10,
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let delivery = finished_delivery(&server, scheduled["id"].as_u64().unwrap()).await;
+    assert_eq!(delivery["state"], "failed");
+    let statuses: Vec<_> = delivery["attempts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["status"].as_u64().unwrap())
+        .collect();
+    assert_eq!(statuses, [503, 503, 503]);
+
+    let resp = server
+        .client()
+        .post(server.url("/_webhooks/send"))
+        .json(&serde_json::json!({"url": "https://example.com/hook"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let server = TestServer::start().unwrap();
+    let resp = server
+        .client()
+        .post(server.url("/_webhooks/send"))
+        .json(&serde_json::json!({"url": server.url("/anything")}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND, "off by default");
+}